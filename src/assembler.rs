@@ -0,0 +1,1705 @@
+use crate::instructions::*;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Where in the source a diagnostic points. Lines and columns count from 1, columns in
+/// characters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+impl Span {
+    /// A whole line, leading and trailing whitespace excluded
+    fn of_line(line: usize, text: &str) -> Self {
+        let trimmed = text.trim_start();
+        Span {
+            line,
+            column: text.chars().count() - trimmed.chars().count() + 1,
+            length: trimmed.trim_end().chars().count(),
+        }
+    }
+
+    /// From the start of `self` to the end of `end`, which is further along the same line
+    fn to(self, end: Span) -> Self {
+        Span {
+            length: end.column + end.length - self.column,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// An assembler error or warning. It displays in the "file:line:column: severity: message"
+/// format editors pick up, followed by the source line with the offending text underlined:
+///
+/// ```text
+/// ws2812.pio:7:5: error: unknown instruction 'jpm'
+/// 7 |     jpm bitloop side 1 [4]
+///   |     ^^^
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: String,
+    pub span: Span,
+    pub message: String,
+    /// The line `span` points into
+    pub source_line: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.file, self.span.line, self.span.column, self.severity, self.message
+        )?;
+        let line_no = self.span.line.to_string();
+        writeln!(f, "{} | {}", line_no, self.source_line)?;
+        // Tabs are kept so the underline lines up however wide the editor shows them
+        let indent: String = self
+            .source_line
+            .chars()
+            .take(self.span.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(
+            f,
+            "{} | {}{}",
+            " ".repeat(line_no.len()),
+            indent,
+            "^".repeat(self.span.length.max(1))
+        )
+    }
+}
+
+/// An error or warning before it is tied to a file
+#[derive(Debug, Clone)]
+struct Issue {
+    span: Span,
+    message: std::string::String,
+}
+
+impl Issue {
+    fn new(span: Span, message: std::string::String) -> Self {
+        Issue { span, message }
+    }
+
+    fn error<T>(span: Span, message: std::string::String) -> Result<T, Issue> {
+        Err(Issue::new(span, message))
+    }
+
+    fn into_diagnostic(self, severity: Severity, file: &str, lines: &[&str]) -> Diagnostic {
+        Diagnostic {
+            severity,
+            file: file.to_string(),
+            source_line: lines
+                .get(self.span.line.wrapping_sub(1))
+                .map_or(std::string::String::new(), |line| line.to_string()),
+            span: self.span,
+            message: self.message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Ident(String),
+    Int(i64),
+    Punct(&'static str),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+    length: usize,
+}
+
+// Longer operators first so "::" is not read as two ":"
+const PUNCTUATION: [&str; 20] = [
+    "::", "--", "!=", "<<", ">>", ",", ":", "[", "]", "(", ")", "+", "-", "*", "/", "!", "~", "|",
+    "&", "^",
+];
+
+/// Blanks out `;`, `//` and `/* */` comments, keeping line and column positions intact
+fn strip_comments(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut in_block = false;
+    let mut in_line = false;
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            in_line = false;
+            stripped.push(c);
+        } else if in_block {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                in_block = false;
+                stripped.push(' ');
+            }
+            stripped.push(' ');
+        } else if in_line {
+            stripped.push(' ');
+        } else if c == ';' || (c == '/' && chars.peek() == Some(&'/')) {
+            in_line = true;
+            stripped.push(' ');
+        } else if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            in_block = true;
+            stripped.push_str("  ");
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+fn parse_int(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn tokenize(line: &str, line_no: usize) -> Result<Vec<Token>, Issue> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = line.chars().collect();
+    let mut idx = 0;
+    while idx < chars.len() {
+        let c = chars[idx];
+        let column = idx + 1;
+        if c.is_whitespace() {
+            idx += 1;
+        } else if c.is_ascii_alphanumeric()
+            || c == '_'
+            || (c == '.' && idx + 1 < chars.len() && chars[idx + 1].is_ascii_alphabetic())
+        {
+            let start = idx;
+            idx += 1;
+            while idx < chars.len() && (chars[idx].is_ascii_alphanumeric() || chars[idx] == '_') {
+                idx += 1;
+            }
+            let text: String = chars[start..idx].iter().collect();
+            let length = idx - start;
+            let kind = if c.is_ascii_digit() {
+                let span = Span {
+                    line: line_no,
+                    column,
+                    length,
+                };
+                TokenKind::Int(
+                    parse_int(&text)
+                        .ok_or_else(|| Issue::new(span, format!("invalid number '{}'", text)))?,
+                )
+            } else {
+                TokenKind::Ident(text)
+            };
+            tokens.push(Token {
+                kind,
+                column,
+                length,
+            });
+        } else {
+            let rest: String = chars[idx..].iter().collect();
+            let punct = PUNCTUATION
+                .iter()
+                .find(|punct| rest.starts_with(*punct))
+                .ok_or_else(|| {
+                    let span = Span {
+                        line: line_no,
+                        column,
+                        length: 1,
+                    };
+                    Issue::new(span, format!("unexpected character '{}'", c))
+                })?;
+            idx += punct.len();
+            tokens.push(Token {
+                kind: TokenKind::Punct(punct),
+                column,
+                length: punct.len(),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expression {
+    Value(i64),
+    Symbol(String),
+    Negate(Box<Expression>),
+    Reverse(Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    fn evaluate(
+        &self,
+        lookup: &dyn Fn(&str) -> Result<i64, std::string::String>,
+    ) -> Result<i64, std::string::String> {
+        match self {
+            Expression::Value(value) => Ok(*value),
+            Expression::Symbol(name) => lookup(name),
+            Expression::Negate(expression) => Ok(-expression.evaluate(lookup)?),
+            Expression::Reverse(expression) => {
+                Ok((expression.evaluate(lookup)? as u32).reverse_bits() as i64)
+            }
+            Expression::Binary(op, lhs, rhs) => {
+                let lhs = lhs.evaluate(lookup)?;
+                let rhs = rhs.evaluate(lookup)?;
+                match *op {
+                    "+" => Ok(lhs.wrapping_add(rhs)),
+                    "-" => Ok(lhs.wrapping_sub(rhs)),
+                    "*" => Ok(lhs.wrapping_mul(rhs)),
+                    "/" if rhs == 0 => Err("division by zero".to_string()),
+                    "/" => Ok(lhs / rhs),
+                    "|" => Ok(lhs | rhs),
+                    "&" => Ok(lhs & rhs),
+                    "^" => Ok(lhs ^ rhs),
+                    "<<" => Ok(lhs.wrapping_shl(rhs as u32)),
+                    ">>" => Ok(lhs.wrapping_shr(rhs as u32)),
+                    _ => Err(format!("unknown operator '{}'", op)),
+                }
+            }
+        }
+    }
+}
+
+/// An expression along with the source text it was parsed from
+#[derive(Debug, Clone)]
+struct Operand {
+    expression: Expression,
+    span: Span,
+}
+
+/// Operands still holding expressions, resolved once every label is known
+#[derive(Debug, Clone)]
+enum Operation {
+    Jmp {
+        condition: JmpCondition,
+        target: Operand,
+    },
+    Wait {
+        polarity: Operand,
+        source: WaitSource,
+        index: Operand,
+        relative: bool,
+    },
+    In {
+        source: InSource,
+        bit_count: Operand,
+    },
+    Out {
+        destination: OutDestination,
+        bit_count: Operand,
+    },
+    Push {
+        if_full: bool,
+        block: bool,
+    },
+    Pull {
+        if_empty: bool,
+        block: bool,
+    },
+    Mov {
+        destination: MovDestination,
+        op: MovOp,
+        source: MovSource,
+    },
+    Irq {
+        clear: bool,
+        wait: bool,
+        index: Operand,
+        relative: bool,
+    },
+    Set {
+        destination: SetDestination,
+        data: Operand,
+    },
+    /// Raw instruction word from `.word`
+    Word(Operand),
+}
+
+#[derive(Debug, Clone)]
+struct Statement {
+    /// The mnemonic or `.word`
+    span: Span,
+    operation: Operation,
+    side: Option<Operand>,
+    delay: Option<Operand>,
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// The token at `pos`, or just past the last token at the end of the line
+    fn span_at(&self, pos: usize) -> Span {
+        let (column, length) = match (self.tokens.get(pos), self.tokens.last()) {
+            (Some(token), _) => (token.column, token.length),
+            (None, Some(last)) => (last.column + last.length, 1),
+            (None, None) => (1, 1),
+        };
+        Span {
+            line: self.line,
+            column,
+            length,
+        }
+    }
+
+    fn span(&self) -> Span {
+        self.span_at(self.pos)
+    }
+
+    /// Points at the token under the cursor
+    fn error<T>(&self, message: std::string::String) -> Result<T, Issue> {
+        Issue::error(self.span(), message)
+    }
+
+    /// Points at the token just consumed
+    fn error_previous<T>(&self, message: std::string::String) -> Result<T, Issue> {
+        Issue::error(self.span_at(self.pos.saturating_sub(1)), message)
+    }
+
+    fn peek(&self) -> Option<&'a TokenKind> {
+        self.tokens.get(self.pos).map(|token| &token.kind)
+    }
+
+    fn next(&mut self) -> Option<&'a TokenKind> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    /// Lowercased identifier at the cursor, keywords are case insensitive
+    fn peek_keyword(&self) -> Option<std::string::String> {
+        match self.peek() {
+            Some(TokenKind::Ident(ident)) => Some(ident.to_ascii_lowercase()),
+            _ => None,
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_keyword().as_deref() == Some(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(TokenKind::Punct(other)) if *other == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), Issue> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{}'", punct))
+        }
+    }
+
+    /// pioasm allows the comma between operands to be left out
+    fn optional_comma(&mut self) {
+        self.eat_punct(",");
+    }
+
+    fn keyword_operand<T: Copy>(&mut self, what: &str, options: &[(&str, T)]) -> Result<T, Issue> {
+        let keyword = self.peek_keyword();
+        match options
+            .iter()
+            .find(|(name, _)| Some(*name) == keyword.as_deref())
+        {
+            Some((_, value)) => {
+                self.pos += 1;
+                Ok(*value)
+            }
+            None => self.error(format!("expected {}", what)),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression, Issue> {
+        self.binary(0)
+    }
+
+    fn operand(&mut self) -> Result<Operand, Issue> {
+        let start = self.span();
+        let expression = self.expression()?;
+        Ok(Operand {
+            expression,
+            span: start.to(self.span_at(self.pos - 1)),
+        })
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expression, Issue> {
+        const LEVELS: [&[&str]; 6] = [
+            &["|"],
+            &["^"],
+            &["&"],
+            &["<<", ">>"],
+            &["+", "-"],
+            &["*", "/"],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some(TokenKind::Punct(op)) if LEVELS[level].contains(op) => *op,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, Issue> {
+        match self.next() {
+            Some(TokenKind::Int(value)) => Ok(Expression::Value(*value)),
+            Some(TokenKind::Ident(name)) => Ok(Expression::Symbol(name.clone())),
+            Some(TokenKind::Punct("-")) => Ok(Expression::Negate(Box::new(self.unary()?))),
+            Some(TokenKind::Punct("::")) => Ok(Expression::Reverse(Box::new(self.unary()?))),
+            Some(TokenKind::Punct("(")) => {
+                let expression = self.expression()?;
+                self.expect_punct(")")?;
+                Ok(expression)
+            }
+            _ => {
+                self.pos -= 1;
+                self.error("expected an expression".to_string())
+            }
+        }
+    }
+
+    fn operation(&mut self, mnemonic: &str) -> Result<Operation, Issue> {
+        match mnemonic {
+            "nop" => Ok(Operation::Mov {
+                destination: MovDestination::Y,
+                op: MovOp::None,
+                source: MovSource::Y,
+            }),
+            "jmp" => {
+                let condition = if self.eat_punct("!") {
+                    self.keyword_operand(
+                        "x, y or osre",
+                        &[
+                            ("x", JmpCondition::ScratchXZero),
+                            ("y", JmpCondition::ScratchYZero),
+                            ("osre", JmpCondition::OutputShiftRegisterNotEmpty),
+                        ],
+                    )?
+                } else if self.eat_keyword("pin") {
+                    JmpCondition::BranchOnInputPin
+                } else if matches!(self.peek_keyword().as_deref(), Some("x") | Some("y")) {
+                    let register = self.peek_keyword();
+                    let start = self.span();
+                    self.pos += 1;
+                    match (register.as_deref(), self.next()) {
+                        (Some("x"), Some(TokenKind::Punct("--"))) => {
+                            JmpCondition::ScratchXNonZeroPostDecrement
+                        }
+                        (Some("y"), Some(TokenKind::Punct("--"))) => {
+                            JmpCondition::ScratchYNonZeroPostDecrement
+                        }
+                        (Some("x"), Some(TokenKind::Punct("!="))) if self.eat_keyword("y") => {
+                            JmpCondition::ScratchXNotEqualScratchY
+                        }
+                        _ => {
+                            let span = start.to(self.span_at(self.pos.min(self.tokens.len()) - 1));
+                            return Issue::error(span, "invalid jmp condition".to_string());
+                        }
+                    }
+                } else {
+                    JmpCondition::Always
+                };
+                self.optional_comma();
+                Ok(Operation::Jmp {
+                    condition,
+                    target: self.operand()?,
+                })
+            }
+            "wait" => {
+                let polarity = match self.peek_keyword().as_deref() {
+                    Some("gpio") | Some("pin") | Some("irq") => Operand {
+                        expression: Expression::Value(1),
+                        span: self.span(),
+                    },
+                    _ => self.operand()?,
+                };
+                let source = self.keyword_operand(
+                    "gpio, pin or irq",
+                    &[
+                        ("gpio", WaitSource::GPIO),
+                        ("pin", WaitSource::Pin),
+                        ("irq", WaitSource::IRQ),
+                    ],
+                )?;
+                self.optional_comma();
+                let index = self.operand()?;
+                let relative = self.eat_keyword("rel");
+                if relative && source != WaitSource::IRQ {
+                    return self.error_previous("rel is only valid for wait irq".to_string());
+                }
+                Ok(Operation::Wait {
+                    polarity,
+                    source,
+                    index,
+                    relative,
+                })
+            }
+            "in" => {
+                let source = self.keyword_operand(
+                    "in source",
+                    &[
+                        ("pins", InSource::PINS),
+                        ("x", InSource::X),
+                        ("y", InSource::Y),
+                        ("null", InSource::NULL),
+                        ("isr", InSource::ISR),
+                        ("osr", InSource::OSR),
+                    ],
+                )?;
+                self.optional_comma();
+                Ok(Operation::In {
+                    source,
+                    bit_count: self.operand()?,
+                })
+            }
+            "out" => {
+                let destination = self.keyword_operand(
+                    "out destination",
+                    &[
+                        ("pins", OutDestination::PINS),
+                        ("x", OutDestination::X),
+                        ("y", OutDestination::Y),
+                        ("null", OutDestination::NULL),
+                        ("pindirs", OutDestination::PINDIRS),
+                        ("pc", OutDestination::PC),
+                        ("isr", OutDestination::ISR),
+                        ("exec", OutDestination::EXEC),
+                    ],
+                )?;
+                self.optional_comma();
+                Ok(Operation::Out {
+                    destination,
+                    bit_count: self.operand()?,
+                })
+            }
+            "push" | "pull" => {
+                let mut conditional = false;
+                let mut block = true;
+                loop {
+                    if (mnemonic == "push" && self.eat_keyword("iffull"))
+                        || (mnemonic == "pull" && self.eat_keyword("ifempty"))
+                    {
+                        conditional = true;
+                    } else if self.eat_keyword("block") {
+                        block = true;
+                    } else if self.eat_keyword("noblock") {
+                        block = false;
+                    } else {
+                        break;
+                    }
+                }
+                if mnemonic == "push" {
+                    Ok(Operation::Push {
+                        if_full: conditional,
+                        block,
+                    })
+                } else {
+                    Ok(Operation::Pull {
+                        if_empty: conditional,
+                        block,
+                    })
+                }
+            }
+            "mov" => {
+                let destination = self.keyword_operand(
+                    "mov destination",
+                    &[
+                        ("pins", MovDestination::PINS),
+                        ("x", MovDestination::X),
+                        ("y", MovDestination::Y),
+                        ("exec", MovDestination::EXEC),
+                        ("pc", MovDestination::PC),
+                        ("isr", MovDestination::ISR),
+                        ("osr", MovDestination::OSR),
+                    ],
+                )?;
+                self.optional_comma();
+                let op = if self.eat_punct("!") || self.eat_punct("~") {
+                    MovOp::Invert
+                } else if self.eat_punct("::") {
+                    MovOp::BitReverse
+                } else {
+                    MovOp::None
+                };
+                // MovSource names 7 after the EXEC encoding of OUT, for MOV it reads the OSR
+                let source = self.keyword_operand(
+                    "mov source",
+                    &[
+                        ("pins", MovSource::PINS),
+                        ("x", MovSource::X),
+                        ("y", MovSource::Y),
+                        ("null", MovSource::NULL),
+                        ("status", MovSource::STATUS),
+                        ("isr", MovSource::ISR),
+                        ("osr", MovSource::EXEC),
+                    ],
+                )?;
+                Ok(Operation::Mov {
+                    destination,
+                    op,
+                    source,
+                })
+            }
+            "irq" => {
+                let (clear, wait) = if self.eat_keyword("clear") {
+                    (true, false)
+                } else if self.eat_keyword("wait") {
+                    (false, true)
+                } else {
+                    // "set" and "nowait" both just raise the flag
+                    let _ = self.eat_keyword("set") || self.eat_keyword("nowait");
+                    (false, false)
+                };
+                let index = self.operand()?;
+                let relative = self.eat_keyword("rel");
+                Ok(Operation::Irq {
+                    clear,
+                    wait,
+                    index,
+                    relative,
+                })
+            }
+            "set" => {
+                let destination = self.keyword_operand(
+                    "set destination",
+                    &[
+                        ("pins", SetDestination::PINS),
+                        ("x", SetDestination::X),
+                        ("y", SetDestination::Y),
+                        ("pindirs", SetDestination::PINDIRS),
+                    ],
+                )?;
+                self.optional_comma();
+                Ok(Operation::Set {
+                    destination,
+                    data: self.operand()?,
+                })
+            }
+            _ => self.error_previous(format!("unknown instruction '{}'", mnemonic)),
+        }
+    }
+}
+
+fn check_range(span: Span, what: &str, value: i64, min: i64, max: i64) -> Result<u16, Issue> {
+    if value < min || value > max {
+        return Issue::error(
+            span,
+            format!("{} {} is out of range {}..={}", what, value, min, max),
+        );
+    }
+    Ok(value as u16)
+}
+
+#[derive(Debug, Clone)]
+struct SymbolDefinition {
+    value: Operand,
+    is_label: bool,
+    public: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Symbols {
+    definitions: BTreeMap<String, SymbolDefinition>,
+    order: Vec<String>,
+}
+
+impl Symbols {
+    fn define(
+        &mut self,
+        span: Span,
+        name: &str,
+        definition: SymbolDefinition,
+    ) -> Result<(), Issue> {
+        if self.definitions.contains_key(name) {
+            return Issue::error(span, format!("'{}' is already defined", name));
+        }
+        self.definitions.insert(name.to_string(), definition);
+        self.order.push(name.to_string());
+        Ok(())
+    }
+}
+
+/// Symbol lookup for one program, falling back to the defines made before the first `.program`
+struct Scope<'a> {
+    program: &'a Symbols,
+    global: &'a Symbols,
+}
+
+impl<'a> Scope<'a> {
+    // Deep enough for any sensible chain of defines, shallow enough to catch cycles quickly
+    const MAX_DEPTH: usize = 64;
+
+    fn value(&self, name: &str, depth: usize) -> Result<i64, std::string::String> {
+        if depth > Self::MAX_DEPTH {
+            return Err(format!("circular definition of '{}'", name));
+        }
+        let definition = self
+            .program
+            .definitions
+            .get(name)
+            .or_else(|| self.global.definitions.get(name))
+            .ok_or(format!("undefined symbol '{}'", name))?;
+        definition
+            .value
+            .expression
+            .evaluate(&|name| self.value(name, depth + 1))
+    }
+
+    fn evaluate(&self, operand: &Operand) -> Result<i64, Issue> {
+        operand
+            .expression
+            .evaluate(&|name| self.value(name, 0))
+            .map_err(|err| Issue::new(operand.span, err))
+    }
+
+    fn export(&self, symbols: &Symbols) -> Result<Vec<Symbol>, Issue> {
+        symbols
+            .order
+            .iter()
+            .map(|name| {
+                let definition = &symbols.definitions[name];
+                Ok(Symbol {
+                    name: name.clone(),
+                    value: self.evaluate(&definition.value)?,
+                    is_label: definition.is_label,
+                    public: definition.public,
+                })
+            })
+            .collect()
+    }
+}
+
+/// One `.program` block before encoding
+#[derive(Debug, Default)]
+struct ProgramSource {
+    name: String,
+    side_set: Option<(Operand, bool, bool)>,
+    origin: Option<Operand>,
+    wrap_target: Option<usize>,
+    wrap: Option<usize>,
+    statements: Vec<Statement>,
+    symbols: Symbols,
+    lang_opts: Vec<LangOpt>,
+    code_blocks: Vec<(String, String)>,
+}
+
+/// Every program of a `.pio` file along with the public defines made outside of them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Assembly {
+    pub symbols: Vec<Symbol>,
+    pub programs: Vec<Program>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Assembly {
+    pub fn program(&self, name: &str) -> Option<&Program> {
+        self.programs.iter().find(|program| program.name == name)
+    }
+}
+
+fn parse(source: &str) -> Result<(Symbols, Vec<ProgramSource>), Issue> {
+    let mut global = Symbols::default();
+    let mut programs: Vec<ProgramSource> = Vec::new();
+    // Open `% lang {` block as (lang, text, opening line)
+    let mut code_block: Option<(String, String, Span)> = None;
+
+    let raw_lines: Vec<&str> = source.lines().collect();
+    for (line_idx, line) in strip_comments(source).lines().enumerate() {
+        let line_no = line_idx + 1;
+        let line_span = Span::of_line(line_no, raw_lines[line_idx]);
+
+        // Code blocks are copied verbatim, comments included
+        if let Some((lang, text, start)) = code_block.as_mut() {
+            if line.trim() == "%}" {
+                let block = (lang.clone(), text.clone());
+                match programs.last_mut() {
+                    Some(program) => program.code_blocks.push(block),
+                    None => {
+                        return Issue::error(*start, "code block outside of a .program".to_string())
+                    }
+                }
+                code_block = None;
+            } else {
+                text.push_str(raw_lines[line_idx]);
+                text.push('\n');
+            }
+            continue;
+        }
+        if let Some(header) = line.trim().strip_prefix('%') {
+            let lang = header.trim().strip_suffix('{').map(str::trim);
+            match lang {
+                Some(lang) if !lang.is_empty() => {
+                    code_block = Some((lang.to_string(), std::string::String::new(), line_span))
+                }
+                _ => return Issue::error(line_span, "expected '% <lang> {'".to_string()),
+            }
+            continue;
+        }
+
+        // The value of a .lang_opt is free form text, so it is split off before tokenizing
+        let trimmed = line.trim();
+        if trimmed.to_ascii_lowercase().starts_with(".lang_opt") {
+            let rest = trimmed[".lang_opt".len()..].trim();
+            let mut words = rest.splitn(2, char::is_whitespace);
+            let lang = words.next().unwrap_or_default();
+            let (name, value) = match words.next().and_then(|rest| rest.split_once('=')) {
+                Some((name, value)) if !lang.is_empty() && !name.trim().is_empty() => {
+                    (name.trim(), value.trim())
+                }
+                _ => {
+                    return Issue::error(
+                        line_span,
+                        "expected .lang_opt <lang> <name> = <value>".to_string(),
+                    )
+                }
+            };
+            let program = programs.last_mut().ok_or_else(|| {
+                Issue::new(line_span, ".lang_opt outside of a .program".to_string())
+            })?;
+            program.lang_opts.push(LangOpt {
+                lang: lang.to_string(),
+                name: name.to_string(),
+                value: value.trim_matches('"').to_string(),
+            });
+            continue;
+        }
+
+        let tokens = tokenize(line, line_no)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            line: line_no,
+        };
+
+        // Labels, optionally public, come before anything else on the line
+        let public = parser.peek_keyword().as_deref() == Some("public");
+        let label_start = public as usize;
+        if let (Some(TokenKind::Ident(label)), Some(TokenKind::Punct(":"))) = (
+            tokens.get(label_start).map(|token| &token.kind),
+            tokens.get(label_start + 1).map(|token| &token.kind),
+        ) {
+            if programs.is_empty() {
+                programs.push(ProgramSource {
+                    name: "program".to_string(),
+                    ..ProgramSource::default()
+                });
+            }
+            let program = programs.last_mut().unwrap();
+            let span = parser.span_at(label_start);
+            let definition = SymbolDefinition {
+                value: Operand {
+                    expression: Expression::Value(program.statements.len() as i64),
+                    span,
+                },
+                is_label: true,
+                public,
+            };
+            program.symbols.define(span, label, definition)?;
+            parser.pos = label_start + 2;
+        }
+
+        let word = match parser.next() {
+            Some(TokenKind::Ident(word)) => word.to_ascii_lowercase(),
+            Some(_) => {
+                return parser.error_previous("expected an instruction or directive".to_string())
+            }
+            None => continue,
+        };
+        let word_span = parser.span_at(parser.pos - 1);
+
+        if word == ".program" {
+            let name = match parser.next() {
+                Some(TokenKind::Ident(name)) => name.clone(),
+                _ => return parser.error_previous("expected a program name".to_string()),
+            };
+            if programs.iter().any(|program| program.name == name) {
+                return parser.error_previous(format!("duplicate program '{}'", name));
+            }
+            programs.push(ProgramSource {
+                name,
+                ..ProgramSource::default()
+            });
+        } else if word == ".define" {
+            let public = parser.eat_keyword("public");
+            let name = match parser.next() {
+                Some(TokenKind::Ident(name)) => name.clone(),
+                _ => return parser.error_previous("expected a symbol name".to_string()),
+            };
+            let name_span = parser.span_at(parser.pos - 1);
+            let definition = SymbolDefinition {
+                value: parser.operand()?,
+                is_label: false,
+                public,
+            };
+            match programs.last_mut() {
+                Some(program) => program.symbols.define(name_span, &name, definition)?,
+                None => global.define(name_span, &name, definition)?,
+            }
+        } else {
+            if programs.is_empty() {
+                // pioasm insists on a .program, a lone snippet is accepted as an unnamed one
+                programs.push(ProgramSource {
+                    name: "program".to_string(),
+                    ..ProgramSource::default()
+                });
+            }
+            let program = programs.last_mut().unwrap();
+            let index = program.statements.len();
+            match word.as_str() {
+                ".side_set" | ".origin" if index != 0 => {
+                    return Issue::error(
+                        word_span,
+                        format!("{} must come before any instruction", word),
+                    )
+                }
+                ".side_set" if program.side_set.is_some() => {
+                    return Issue::error(word_span, "duplicate .side_set".to_string())
+                }
+                ".side_set" => {
+                    let count = parser.operand()?;
+                    let optional = parser.eat_keyword("opt");
+                    let pindirs = parser.eat_keyword("pindirs");
+                    program.side_set = Some((count, optional, pindirs));
+                }
+                ".origin" => program.origin = Some(parser.operand()?),
+                ".wrap_target" if program.wrap_target.is_some() => {
+                    return Issue::error(word_span, "duplicate .wrap_target".to_string())
+                }
+                ".wrap_target" => program.wrap_target = Some(index),
+                ".wrap" if program.wrap.is_some() => {
+                    return Issue::error(word_span, "duplicate .wrap".to_string())
+                }
+                ".wrap" if index == 0 => {
+                    return Issue::error(word_span, ".wrap must follow an instruction".to_string())
+                }
+                ".wrap" => program.wrap = Some(index - 1),
+                ".word" => {
+                    let operation = Operation::Word(parser.operand()?);
+                    program.statements.push(Statement {
+                        span: word_span,
+                        operation,
+                        side: None,
+                        delay: None,
+                    });
+                }
+                _ if word.starts_with('.') => {
+                    return Issue::error(word_span, format!("unknown directive '{}'", word))
+                }
+                _ => {
+                    let operation = parser.operation(&word)?;
+                    let mut side = None;
+                    let mut delay = None;
+                    while !parser.at_end() {
+                        if side.is_none()
+                            && (parser.eat_keyword("side") || parser.eat_keyword("sideset"))
+                        {
+                            side = Some(parser.operand()?);
+                        } else if delay.is_none() && parser.eat_punct("[") {
+                            delay = Some(parser.operand()?);
+                            parser.expect_punct("]")?;
+                        } else {
+                            break;
+                        }
+                    }
+                    program.statements.push(Statement {
+                        span: word_span,
+                        operation,
+                        side,
+                        delay,
+                    });
+                }
+            }
+        }
+
+        if !parser.at_end() {
+            return parser.error("unexpected trailing input".to_string());
+        }
+    }
+
+    if let Some((_, _, start)) = code_block {
+        return Issue::error(start, "unterminated code block".to_string());
+    }
+    Ok((global, programs))
+}
+
+/// Resolves the operands of `statement` into the instruction it stands for, leaving the
+/// delay/side-set field empty. `.word` statements have no instruction and are encoded directly.
+fn lower(statement: &Statement, scope: &Scope) -> Result<PIOInstruction, Issue> {
+    let range = |what: &str, operand: &Operand, min: i64, max: i64| {
+        check_range(operand.span, what, scope.evaluate(operand)?, min, max).map(|value| value as u8)
+    };
+    let relative_bit = |relative: bool| if relative { 0x10 } else { 0 };
+
+    let instruction = match &statement.operation {
+        Operation::Jmp { condition, target } => PIOInstruction::JMP {
+            delay_sideset: 0,
+            condition: *condition,
+            address: range("jmp target", target, 0, 31)?,
+        },
+        Operation::Wait {
+            polarity,
+            source,
+            index,
+            relative,
+        } => {
+            let polarity = match range("wait polarity", polarity, 0, 1)? {
+                0 => WaitPolarity::Zero,
+                _ => WaitPolarity::One,
+            };
+            let max = if *source == WaitSource::IRQ { 7 } else { 31 };
+            PIOInstruction::WAIT {
+                delay_sideset: 0,
+                polarity,
+                source: *source,
+                index: range("wait index", index, 0, max)? | relative_bit(*relative),
+            }
+        }
+        // A bit count of 32 is encoded as 0
+        Operation::In { source, bit_count } => PIOInstruction::IN {
+            delay_sideset: 0,
+            source: *source,
+            bit_count: range("bit count", bit_count, 1, 32)? & 0x1f,
+        },
+        Operation::Out {
+            destination,
+            bit_count,
+        } => PIOInstruction::OUT {
+            delay_sideset: 0,
+            destination: *destination,
+            bit_count: range("bit count", bit_count, 1, 32)? & 0x1f,
+        },
+        Operation::Push { if_full, block } => PIOInstruction::PUSH {
+            delay_sideset: 0,
+            if_full: *if_full,
+            block: *block,
+        },
+        Operation::Pull { if_empty, block } => PIOInstruction::PULL {
+            delay_sideset: 0,
+            if_empty: *if_empty,
+            block: *block,
+        },
+        Operation::Mov {
+            destination,
+            op,
+            source,
+        } => PIOInstruction::MOV {
+            delay_sideset: 0,
+            destination: *destination,
+            op: *op,
+            source: *source,
+        },
+        Operation::Irq {
+            clear,
+            wait,
+            index,
+            relative,
+        } => PIOInstruction::IRQ {
+            delay_sideset: 0,
+            clear: *clear,
+            wait: *wait,
+            index: range("irq index", index, 0, 7)? | relative_bit(*relative),
+        },
+        Operation::Set { destination, data } => PIOInstruction::SET {
+            delay_sideset: 0,
+            destination: *destination,
+            data: range("set value", data, 0, 31)?,
+        },
+        Operation::Word(_) => unreachable!("raw .word values are not lowered"),
+    };
+    Ok(instruction)
+}
+
+fn encode(statement: &Statement, side_set: &SideSet, scope: &Scope) -> Result<u16, Issue> {
+    let eval = |operand: &Operand| scope.evaluate(operand);
+    let range = |what: &str, operand: &Operand, min: i64, max: i64| {
+        check_range(operand.span, what, eval(operand)?, min, max)
+    };
+
+    let instruction = match &statement.operation {
+        Operation::Word(value) => return range(".word value", value, 0, 0xFFFF),
+        _ => lower(statement, scope)?,
+    };
+
+    // Checked here as well as by `encode_with_side_set` to point at the operand at fault
+    let side = match &statement.side {
+        Some(side) if side_set.count == 0 => {
+            return Issue::error(side.span, "side-set used without .side_set".to_string())
+        }
+        Some(side) => Some(range("side-set value", side, 0, side_set.max_value() as i64)? as u8),
+        None if side_set.count != 0 && !side_set.optional => {
+            return Issue::error(
+                statement.span,
+                "missing side-set value, the program's .side_set is not optional".to_string(),
+            )
+        }
+        None => None,
+    };
+    let delay = match &statement.delay {
+        Some(delay) => {
+            let value = eval(delay)?;
+            if value < 0 || value > side_set.max_delay() as i64 {
+                return Issue::error(
+                    delay.span,
+                    format!(
+                        "delay {} is out of range 0..={}, {} of the 5 delay bits are used by side-set",
+                        value,
+                        side_set.max_delay(),
+                        side_set.bits()
+                    ),
+                );
+            }
+            value as u8
+        }
+        None => 0,
+    };
+
+    instruction
+        .encode_with_side_set(side_set, side, delay)
+        .map_err(|err| Issue::new(statement.span, err))
+}
+
+fn build(
+    program: &ProgramSource,
+    global: &Symbols,
+    warnings: &mut Vec<Issue>,
+) -> Result<Program, Issue> {
+    let scope = Scope {
+        program: &program.symbols,
+        global,
+    };
+    if let Some(statement) = program.statements.get(32) {
+        return Issue::error(
+            statement.span,
+            format!(
+                "program {} is {} instructions long, instruction memory holds 32",
+                program.name,
+                program.statements.len()
+            ),
+        );
+    }
+
+    let side_set = match &program.side_set {
+        Some((count, optional, pindirs)) => {
+            let max = if *optional { 4 } else { 5 };
+            SideSet {
+                count: check_range(count.span, "side-set count", scope.evaluate(count)?, 0, max)?
+                    as u8,
+                optional: *optional,
+                pindirs: *pindirs,
+            }
+        }
+        None => SideSet::default(),
+    };
+    let origin = match &program.origin {
+        Some(origin) => {
+            Some(check_range(origin.span, ".origin", scope.evaluate(origin)?, 0, 31)? as u8)
+        }
+        None => None,
+    };
+    let instructions = program
+        .statements
+        .iter()
+        .map(|statement| encode(statement, &side_set, &scope))
+        .collect::<Result<Vec<u16>, Issue>>()?;
+    let symbols = scope.export(&program.symbols)?;
+
+    for statement in program.statements.iter() {
+        if let Operation::Jmp { target, .. } = &statement.operation {
+            let address = scope.evaluate(target)?;
+            if address as usize >= instructions.len() {
+                warnings.push(Issue::new(
+                    target.span,
+                    format!(
+                        "jmp target {} is past the end of program {}, which is {} instructions long",
+                        address,
+                        program.name,
+                        instructions.len()
+                    ),
+                ));
+            }
+        }
+    }
+
+    Ok(Program {
+        name: program.name.clone(),
+        wrap_target: program.wrap_target.unwrap_or(0) as u8,
+        wrap: program
+            .wrap
+            .unwrap_or_else(|| instructions.len().saturating_sub(1)) as u8,
+        instructions,
+        origin,
+        side_set,
+        symbols,
+        lang_opts: program.lang_opts.clone(),
+        code_blocks: program.code_blocks.clone(),
+    })
+}
+
+/// Assembles every program in pioasm source, naming `file` in diagnostics. Assembly stops at
+/// the first error, warnings are collected in the returned `Assembly`.
+pub fn assemble_file(file: &str, source: &str) -> Result<Assembly, Diagnostic> {
+    let lines: Vec<&str> = source.lines().collect();
+    let error = |issue: Issue| issue.into_diagnostic(Severity::Error, file, &lines);
+
+    let (global, programs) = parse(source).map_err(error)?;
+    let empty = Symbols::default();
+    let global_scope = Scope {
+        program: &empty,
+        global: &global,
+    };
+
+    let mut warnings = Vec::new();
+    let symbols = global_scope.export(&global).map_err(error)?;
+    let programs = programs
+        .iter()
+        .map(|program| build(program, &global, &mut warnings))
+        .collect::<Result<Vec<Program>, Issue>>()
+        .map_err(error)?;
+
+    Ok(Assembly {
+        symbols,
+        programs,
+        warnings: warnings
+            .into_iter()
+            .map(|issue| issue.into_diagnostic(Severity::Warning, file, &lines))
+            .collect(),
+    })
+}
+
+/// Assembles every program in pioasm source. Errors are rendered as by `assemble_file`.
+pub fn assemble(source: &str) -> Result<Assembly, std::string::String> {
+    assemble_file("<input>", source).map_err(|err| err.to_string())
+}
+
+/// Assembles source holding a single program
+pub fn assemble_program(source: &str) -> Result<Program, std::string::String> {
+    let mut assembly = assemble(source)?;
+    match assembly.programs.len() {
+        1 => Ok(assembly.programs.remove(0)),
+        count => Err(format!("expected a single program, found {}", count)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Vec<u16> {
+        assemble_program(source).unwrap().instructions
+    }
+
+    #[test]
+    fn ws2812_matches_pioasm() {
+        let program = assemble_program(
+            ".program ws2812
+.side_set 1
+
+.define public T1 2
+.define public T2 5
+.define public T3 3
+
+.wrap_target
+bitloop:
+    out x, 1       side 0 [T3 - 1]
+    jmp !x do_zero side 1 [T1 - 1]
+do_one:
+    jmp  bitloop   side 1 [T2 - 1]
+do_zero:
+    nop            side 0 [T2 - 1]
+.wrap",
+        )
+        .unwrap();
+        assert_eq!(program.instructions, vec![0x6221, 0x1123, 0x1400, 0xa442]);
+        assert_eq!((program.wrap_target, program.wrap), (0, 3));
+    }
+
+    #[test]
+    fn jmp_conditions_and_labels() {
+        assert_eq!(
+            words(
+                "start:
+    jmp 3
+    jmp !x 3
+    jmp x-- 3
+    jmp !y 3
+    jmp y-- 3
+    jmp x!=y 3
+    jmp pin 3
+    jmp !osre, start
+    jmp end
+end:"
+            ),
+            vec![0x0003, 0x0023, 0x0043, 0x0063, 0x0083, 0x00a3, 0x00c3, 0x00e0, 0x0009]
+        );
+    }
+
+    #[test]
+    fn wait_in_out_and_set_operands() {
+        assert_eq!(
+            words(
+                "wait 0 pin 3
+    wait 1 gpio 2
+    wait 1 irq 2 rel
+    in pins, 32
+    in x, 5
+    in osr, 1
+    out pindirs, 4
+    out pc, 5
+    out exec, 16
+    set pindirs, 1
+    set y, 31"
+            ),
+            vec![
+                0x2023, 0x2082, 0x20d2, 0x4000, 0x4025, 0x40e1, 0x6084, 0x60a5, 0x60f0, 0xe081,
+                0xe05f
+            ]
+        );
+    }
+
+    #[test]
+    fn push_pull_mov_and_irq_flags() {
+        assert_eq!(
+            words(
+                "push
+    push iffull noblock
+    pull noblock
+    pull ifempty block
+    mov x, ~status
+    mov pins, ::isr
+    mov osr, null
+    mov isr, osr
+    nop
+    irq 3
+    irq set 3
+    irq wait 1 rel
+    irq clear 7"
+            ),
+            vec![
+                0x8020, 0x8040, 0x8080, 0x80e0, 0xa02d, 0xa016, 0xa0e3, 0xa0c7, 0xa042, 0xc003,
+                0xc003, 0xc031, 0xc047
+            ]
+        );
+    }
+
+    #[test]
+    fn expressions_and_raw_words() {
+        assert_eq!(
+            words(
+                ".define BASE 0b100
+    set x, (1 + 2) * 3
+    set y, BASE << 2 | 1
+    set pins, -(-7) & 0x1f
+    .word 0xbeef"
+            ),
+            vec![0xe029, 0xe051, 0xe007, 0xbeef]
+        );
+    }
+
+    #[test]
+    fn operands_out_of_range_are_rejected() {
+        for (source, message) in [
+            ("set x, 32", "set value 32 is out of range"),
+            ("in x, 0", "bit count 0 is out of range"),
+            ("wait 2 pin 0", "wait polarity 2 is out of range"),
+            ("irq 8", "irq index 8 is out of range"),
+            ("jmp 32", "jmp target 32 is out of range"),
+            (".word 0x10000", ".word value 65536 is out of range"),
+        ] {
+            let err = assemble_program(source).unwrap_err();
+            assert!(err.contains(message), "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn wrap_and_origin_directives() {
+        let program = assemble_program(
+            ".program looped
+.origin 4
+    set pins, 0
+.wrap_target
+    set pins, 1
+    set pins, 0
+.wrap
+    jmp 0",
+        )
+        .unwrap();
+        assert_eq!(program.origin, Some(4));
+        assert_eq!((program.wrap_target, program.wrap), (1, 2));
+
+        let program = assemble_program("set pins, 0\nset pins, 1").unwrap();
+        assert_eq!(program.origin, None);
+        assert_eq!((program.wrap_target, program.wrap), (0, 1));
+    }
+
+    #[test]
+    fn optional_pindirs_side_set_takes_an_enable_bit() {
+        let program = assemble_program(
+            ".program dirs
+.side_set 1 opt pindirs
+    set x, 1 side 1 [3]
+    nop
+    nop [7]",
+        )
+        .unwrap();
+        assert_eq!(
+            program.side_set,
+            SideSet {
+                count: 1,
+                optional: true,
+                pindirs: true,
+            }
+        );
+        assert_eq!(program.instructions, vec![0xfb21, 0xa042, 0xa742]);
+    }
+
+    #[test]
+    fn defines_are_scoped_and_exported() {
+        let assembly = assemble(
+            ".define public PUBLIC 3
+.define PRIVATE PUBLIC + 1
+.program first
+.define public LOCAL PRIVATE * 2
+public entry:
+    set x, LOCAL
+.program second
+    set x, PRIVATE",
+        )
+        .unwrap();
+        let exported = |symbols: &[Symbol]| -> Vec<(String, i64, bool, bool)> {
+            symbols
+                .iter()
+                .map(|symbol| {
+                    (
+                        symbol.name.clone(),
+                        symbol.value,
+                        symbol.is_label,
+                        symbol.public,
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(
+            exported(&assembly.symbols),
+            vec![
+                ("PUBLIC".to_string(), 3, false, true),
+                ("PRIVATE".to_string(), 4, false, false),
+            ]
+        );
+        let first = assembly.program("first").unwrap();
+        assert_eq!(
+            exported(&first.symbols),
+            vec![
+                ("LOCAL".to_string(), 8, false, true),
+                ("entry".to_string(), 0, true, true),
+            ]
+        );
+        assert_eq!(first.instructions, vec![0xe028]);
+        assert_eq!(
+            assembly.program("second").unwrap().instructions,
+            vec![0xe024]
+        );
+
+        let err =
+            assemble(".program first\n.define LOCAL 1\n.program second\nset x, LOCAL").unwrap_err();
+        assert!(err.contains("undefined symbol 'LOCAL'"), "{}", err);
+    }
+
+    #[test]
+    fn lang_opts_keep_their_free_form_value() {
+        let program = assemble_program(
+            ".program opts
+.lang_opt python sideset_init = pico.PIO.OUT_HIGH
+.lang_opt c   out_init  = \"a = b\"
+    nop",
+        )
+        .unwrap();
+        assert_eq!(
+            program.lang_opts,
+            vec![
+                LangOpt {
+                    lang: "python".to_string(),
+                    name: "sideset_init".to_string(),
+                    value: "pico.PIO.OUT_HIGH".to_string(),
+                },
+                LangOpt {
+                    lang: "c".to_string(),
+                    name: "out_init".to_string(),
+                    value: "a = b".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn misplaced_directives_are_rejected() {
+        for (source, message) in [
+            ("nop\n.origin 2", ".origin must come before any instruction"),
+            (
+                "nop\n.side_set 1",
+                ".side_set must come before any instruction",
+            ),
+            (".side_set 1\n.side_set 1", "duplicate .side_set"),
+            (".wrap", ".wrap must follow an instruction"),
+            ("nop\n.wrap\n.wrap", "duplicate .wrap"),
+            (".wrap_target\n.wrap_target", "duplicate .wrap_target"),
+            (".origin 32\nnop", ".origin 32 is out of range 0..=31"),
+            (
+                ".side_set 5 opt\nnop",
+                "side-set count 5 is out of range 0..=4",
+            ),
+            (
+                ".lang_opt python",
+                "expected .lang_opt <lang> <name> = <value>",
+            ),
+            (".nope", "unknown directive '.nope'"),
+        ] {
+            let err = assemble(source).unwrap_err();
+            assert!(err.contains(message), "{}: {}", source, err);
+        }
+    }
+
+    fn error_at(source: &str) -> (Span, String) {
+        let err = assemble_file("test.pio", source).unwrap_err();
+        assert_eq!(err.severity, Severity::Error);
+        (err.span, err.message)
+    }
+
+    fn span(line: usize, column: usize, length: usize) -> Span {
+        Span {
+            line,
+            column,
+            length,
+        }
+    }
+
+    #[test]
+    fn diagnostics_render_location_source_and_underline() {
+        let err =
+            assemble_file("ws2812.pio", ".program ws2812\n\tjpm bitloop side 1 [4]").unwrap_err();
+        assert_eq!(err.span, span(2, 2, 3));
+        assert_eq!(
+            err.to_string(),
+            "ws2812.pio:2:2: error: unknown instruction 'jpm'\n\
+             2 | \tjpm bitloop side 1 [4]\n\
+             \x20 | \t^^^"
+        );
+    }
+
+    #[test]
+    fn diagnostics_point_at_the_operand_at_fault() {
+        assert_eq!(
+            error_at("set x, 1 + 40"),
+            (
+                span(1, 8, 6),
+                "set value 41 is out of range 0..=31".to_string()
+            )
+        );
+        assert_eq!(
+            error_at("jmp nowhere"),
+            (span(1, 5, 7), "undefined symbol 'nowhere'".to_string())
+        );
+        assert_eq!(
+            error_at("set x, 1 side 1"),
+            (
+                span(1, 15, 1),
+                "side-set used without .side_set".to_string()
+            )
+        );
+        assert_eq!(
+            error_at(".side_set 1\nnop"),
+            (
+                span(2, 1, 3),
+                "missing side-set value, the program's .side_set is not optional".to_string()
+            )
+        );
+        assert_eq!(
+            error_at(".side_set 1\nnop side 0 [16]"),
+            (
+                span(2, 13, 2),
+                "delay 16 is out of range 0..=15, 1 of the 5 delay bits are used by side-set"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn programs_longer_than_instruction_memory_are_rejected() {
+        let source = format!(".program long\n{}", "    nop\n".repeat(33));
+        assert_eq!(
+            error_at(&source),
+            (
+                span(34, 5, 3),
+                "program long is 33 instructions long, instruction memory holds 32".to_string()
+            )
+        );
+        assert!(assemble(&format!(".program full\n{}", "nop\n".repeat(32))).is_ok());
+    }
+
+    #[test]
+    fn jmp_past_the_end_is_a_warning() {
+        let assembly = assemble_file("test.pio", "jmp 5\nnop").unwrap();
+        assert_eq!(assembly.programs[0].instructions, vec![0x0005, 0xa042]);
+        assert_eq!(assembly.warnings.len(), 1);
+        let warning = &assembly.warnings[0];
+        assert_eq!(warning.severity, Severity::Warning);
+        assert_eq!(warning.span, span(1, 5, 1));
+        assert_eq!(
+            warning.to_string(),
+            "test.pio:1:5: warning: jmp target 5 is past the end of program program, \
+             which is 2 instructions long\n\
+             1 | jmp 5\n  |     ^"
+        );
+    }
+}
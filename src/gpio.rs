@@ -1,52 +1,1147 @@
-#[derive(Debug, Default)]
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+
+use crate::netlist::Netlist;
+use crate::pin_names::{self, PinNames};
+
+/// Anything that can put a value on the pins. Drivers later in this ordering win when several of
+/// them enable the same pin, which matches the PIO giving priority to higher numbered state
+/// machines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GPIODriver {
+    StateMachine {
+        pio: u8,
+        sm: u8,
+    },
+    CPU,
+    /// A net of the board netlist, by its index
+    Net(u32),
+    External(u32),
+}
+
+impl GPIODriver {
+    pub fn pio_block(&self) -> Option<u8> {
+        match self {
+            GPIODriver::StateMachine { pio, .. } => Some(*pio),
+            _ => None,
+        }
+    }
+
+    /// The function a pin has to select for this driver to reach it. Nets and external drivers
+    /// sit on the board side of the pad and always reach it.
+    pub fn function(&self) -> Option<FunctionSelect> {
+        match self {
+            GPIODriver::StateMachine { pio: 0, .. } => Some(FunctionSelect::PIO0),
+            GPIODriver::StateMachine { pio: 1, .. } => Some(FunctionSelect::PIO1),
+            GPIODriver::StateMachine { .. } => Some(FunctionSelect::NULL),
+            GPIODriver::CPU => Some(FunctionSelect::SIO),
+            GPIODriver::Net(_) | GPIODriver::External(_) => None,
+        }
+    }
+}
+
+/// IO_BANK0 `GPIOx_CTRL.FUNCSEL` values that the emulator models
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionSelect {
+    SIO = 5,
+    PIO0 = 6,
+    PIO1 = 7,
+    NULL = 0x1f,
+}
+
+/// IO_BANK0 `GPIOx_CTRL` OUTOVER, OEOVER and INOVER settings
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Override {
+    Normal = 0,
+    Invert = 1,
+    Low = 2,
+    High = 3,
+}
+
+/// Per pin IO_BANK0 control, applied between the peripherals and the pad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PinControl {
+    pub function: FunctionSelect,
+    pub output_override: Override,
+    pub enable_override: Override,
+    pub input_override: Override,
+}
+
+impl Default for PinControl {
+    // The datasheet reset value, no peripheral reaches the pin
+    fn default() -> Self {
+        PinControl {
+            function: FunctionSelect::NULL,
+            output_override: Override::Normal,
+            enable_override: Override::Normal,
+            input_override: Override::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct OverrideMasks {
+    invert: u64,
+    low: u64,
+    high: u64,
+}
+
+impl OverrideMasks {
+    fn apply(&self, value: u64) -> u64 {
+        ((value ^ self.invert) & !self.low) | self.high
+    }
+
+    fn set(&mut self, idx: usize, setting: Override) {
+        let bit = 1 << idx;
+        self.invert &= !bit;
+        self.low &= !bit;
+        self.high &= !bit;
+        match setting {
+            Override::Normal => {}
+            Override::Invert => self.invert |= bit,
+            Override::Low => self.low |= bit,
+            Override::High => self.high |= bit,
+        }
+    }
+}
+
+/// Output value and output enable masks contributed by a single driver, one bit per pin
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DriverState {
+    pub value: u64,
+    pub enable: u64,
+}
+
+impl DriverState {
+    fn merge(self, other: DriverState) -> DriverState {
+        DriverState {
+            value: (self.value & !other.enable) | (other.value & other.enable),
+            enable: self.enable | other.enable,
+        }
+    }
+}
+
+/// Pad pull configuration. Enabling both pulls turns the pad into a bus keeper, like the RP2040.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+    BusKeeper,
+}
+
+/// Two or more enabled drivers disagreeing about the level of a push-pull pin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contention {
+    pub cycle: u64,
+    pub pin: usize,
+    /// Pin name at the time of the contention, "GPIOn" if it has none
+    pub label: String,
+    pub drivers: Vec<GPIODriver>,
+}
+
+impl fmt::Display for Contention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cycle {} : contention on {} between {:?}",
+            self.cycle, self.label, self.drivers
+        )
+    }
+}
+
+/// Which level transitions a subscription is notified about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Any,
+}
+
+impl Edge {
+    fn matches(&self, level: u8) -> bool {
+        match self {
+            Edge::Rising => level == 1,
+            Edge::Falling => level == 0,
+            Edge::Any => true,
+        }
+    }
+}
+
+/// A pad changing level, stamped with the GPIO cycle it happened on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PinEvent {
+    pub pin: usize,
+    pub level: u8,
+    pub cycle: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubscriptionId(u32);
+
+enum Listener {
+    Callback(Box<dyn FnMut(PinEvent) + Send>),
+    Channel(mpsc::Sender<PinEvent>),
+}
+
+struct Subscription {
+    id: SubscriptionId,
+    pins: u64,
+    edge: Edge,
+    listener: Listener,
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let listener = match self.listener {
+            Listener::Callback(_) => "Callback",
+            Listener::Channel(_) => "Channel",
+        };
+        f.debug_struct("Subscription")
+            .field("id", &self.id)
+            .field("pins", &format_args!("{:#x}", self.pins))
+            .field("edge", &self.edge)
+            .field("listener", &listener)
+            .finish()
+    }
+}
+
+/// User GPIOs on the RP2040
+pub const RP2040_PIN_COUNT: usize = 30;
+/// User GPIOs on the QFN-80 RP2350B
+pub const RP2350B_PIN_COUNT: usize = 48;
+/// Largest bank `GPIO` can model, every pin being a bit of a `u64`
+pub const MAX_PIN_COUNT: usize = 64;
+
+#[derive(Debug)]
 pub struct GPIO {
-    inner: [u8; 32],
-    inner_enable: [u8; 32],
+    pin_count: usize,
+    pin_mask: u64,
+    drivers: BTreeMap<GPIODriver, DriverState>,
+    pin_control: Vec<PinControl>,
+    function_pins: BTreeMap<u8, u64>,
+    output_override: OverrideMasks,
+    enable_override: OverrideMasks,
+    input_override: OverrideMasks,
+    resolved: DriverState,
+    levels: u64,
+    external: u64,
+    external_enable: u64,
+    pull_up: u64,
+    pull_down: u64,
+    open_drain: u64,
+    driven: u64,
+    contended: u64,
+    contentions: Vec<Contention>,
+    stimulus: BTreeMap<u64, Vec<(usize, u8)>>,
+    cycle: u64,
+    subscriptions: Vec<Subscription>,
+    next_subscription: u32,
+    netlist: Netlist,
+    names: PinNames,
+}
+
+/// GPIO shared between the PIO blocks, the SIO and the host, possibly on different threads
+pub type SharedGPIO = Arc<Mutex<GPIO>>;
+
+pub fn shared(gpio: GPIO) -> SharedGPIO {
+    Arc::new(Mutex::new(gpio))
+}
+
+/// Locks a shared GPIO. A panic in a pin-change callback poisons the lock, but the pin state is
+/// still usable afterwards, so the poison is ignored.
+pub fn lock(gpio: &SharedGPIO) -> MutexGuard<'_, GPIO> {
+    gpio.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Lists every named group with its value followed by the level of every pin
+impl fmt::Display for GPIO {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, pins) in self.names.groups() {
+            let value = pins.iter().enumerate().fold(0u64, |value, (bit, pin)| {
+                value | ((self.get_idx(*pin) as u64) << bit)
+            });
+            writeln!(f, "{:<16} 0x{:X}", name, value)?;
+        }
+        for pin in 0..self.pin_count {
+            let direction = if self.get_idx_enable(pin) == 1 {
+                "out"
+            } else {
+                "in"
+            };
+            writeln!(
+                f,
+                "GPIO{:<2} {:<16} {} {}",
+                pin,
+                self.names.name(pin).unwrap_or_default(),
+                direction,
+                self.get_idx(pin)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for GPIO {
+    fn default() -> Self {
+        GPIO::new()
+    }
 }
 
 impl GPIO {
+    /// The 32 pins a PIO can address without a window offset, all selected to PIO0 so a single
+    /// PIO block can be emulated without setting up FUNCSEL
     pub fn new() -> Self {
-        GPIO {
-            inner: [0; 32],
-            inner_enable: [0; 32],
+        GPIO::with_pin_count(32, FunctionSelect::PIO0).unwrap()
+    }
+
+    /// Creates a bank of `pin_count` physical pins whose FUNCSEL starts out as `function`. Pass
+    /// `FunctionSelect::NULL` for the datasheet reset state, where no peripheral reaches a pin
+    /// until `set_function` selects one. Pins past the end read as 0 and writes to them are
+    /// ignored.
+    pub fn with_pin_count(
+        pin_count: usize,
+        function: FunctionSelect,
+    ) -> Result<Self, std::string::String> {
+        if pin_count > MAX_PIN_COUNT {
+            return Err(format!("Invalid pin count : {}", pin_count));
         }
+        let pin_mask = if pin_count == 64 {
+            !0
+        } else {
+            (1 << pin_count) - 1
+        };
+
+        Ok(GPIO {
+            pin_count,
+            pin_mask,
+            drivers: BTreeMap::new(),
+            pin_control: vec![
+                PinControl {
+                    function,
+                    ..PinControl::default()
+                };
+                pin_count
+            ],
+            function_pins: std::iter::once((function as u8, pin_mask)).collect(),
+            output_override: OverrideMasks::default(),
+            enable_override: OverrideMasks::default(),
+            input_override: OverrideMasks::default(),
+            resolved: DriverState::default(),
+            levels: 0,
+            external: 0,
+            external_enable: 0,
+            pull_up: 0,
+            pull_down: 0,
+            open_drain: 0,
+            driven: 0,
+            contended: 0,
+            contentions: Vec::new(),
+            stimulus: BTreeMap::new(),
+            cycle: 0,
+            subscriptions: Vec::new(),
+            next_subscription: 0,
+            netlist: Netlist::new(),
+            names: PinNames::new(),
+        })
+    }
+
+    pub fn get_pin_count(&self) -> usize {
+        self.pin_count
     }
 
+    fn pin_bit(&self, idx: usize) -> u64 {
+        if idx < self.pin_count {
+            1 << idx
+        } else {
+            0
+        }
+    }
+
+    /// Level of `idx` as the peripherals see it, after the input override
     pub fn get_idx(&self, idx: usize) -> u8 {
-        self.inner[idx]
+        (self.get_peripheral_input_vector() & self.pin_bit(idx) != 0) as u8
+    }
+
+    pub fn get_peripheral_input_vector(&self) -> u64 {
+        self.input_override.apply(self.levels) & self.pin_mask
+    }
+
+    pub fn get_pin_control(&self, idx: usize) -> PinControl {
+        self.pin_control.get(idx).copied().unwrap_or_default()
+    }
+
+    pub fn set_pin_control(&mut self, idx: usize, control: PinControl) {
+        if idx >= self.pin_count {
+            return;
+        }
+        for pins in self.function_pins.values_mut() {
+            *pins &= !(1 << idx);
+        }
+        *self
+            .function_pins
+            .entry(control.function as u8)
+            .or_default() |= 1 << idx;
+
+        self.pin_control[idx] = control;
+        self.output_override.set(idx, control.output_override);
+        self.enable_override.set(idx, control.enable_override);
+        self.input_override.set(idx, control.input_override);
+        self.resolve();
+    }
+
+    pub fn set_function(&mut self, idx: usize, function: FunctionSelect) {
+        let control = PinControl {
+            function,
+            ..self.get_pin_control(idx)
+        };
+        self.set_pin_control(idx, control);
     }
 
     pub fn get_idx_enable(&self, idx: usize) -> u8 {
-        self.inner_enable[idx]
+        (self.resolved.enable & self.pin_bit(idx) != 0) as u8
+    }
+
+    /// Level of every pad: the driven value where an output is enabled, the external input level
+    /// or pull everywhere else. Floating pins read as 0.
+    pub fn get_level_vector(&self) -> u64 {
+        self.levels
+    }
+
+    pub fn get_input_vector(&self) -> u64 {
+        self.external
+    }
+
+    /// Sets the level an external source applies to `idx`. It is only visible while no driver
+    /// enables its output on that pin.
+    pub fn set_input_level(&mut self, idx: usize, level: u8) {
+        let bit = self.pin_bit(idx);
+        self.set_input_vector(if level & 1 == 1 { bit } else { 0 }, bit);
+    }
+
+    /// Sets the external input level of the pins set in `mask`
+    pub fn set_input_vector(&mut self, value: u64, mask: u64) {
+        let mask = mask & self.pin_mask;
+        self.external = (self.external & !mask) | (value & mask);
+        self.external_enable |= mask;
+        self.resolve();
+    }
+
+    /// Disconnects the external source from `idx`, leaving it to the pulls
+    pub fn release_input(&mut self, idx: usize) {
+        self.external_enable &= !self.pin_bit(idx);
+        self.resolve();
+    }
+
+    pub fn set_pull(&mut self, idx: usize, pull: Pull) {
+        let bit = self.pin_bit(idx);
+        self.pull_up &= !bit;
+        self.pull_down &= !bit;
+        match pull {
+            Pull::None => {}
+            Pull::Up => self.pull_up |= bit,
+            Pull::Down => self.pull_down |= bit,
+            Pull::BusKeeper => {
+                self.pull_up |= bit;
+                self.pull_down |= bit;
+            }
+        }
+        self.resolve();
+    }
+
+    pub fn get_pull(&self, idx: usize) -> Pull {
+        let bit = self.pin_bit(idx);
+        match (self.pull_up & bit != 0, self.pull_down & bit != 0) {
+            (true, true) => Pull::BusKeeper,
+            (true, false) => Pull::Up,
+            (false, true) => Pull::Down,
+            (false, false) => Pull::None,
+        }
+    }
+
+    /// Makes `idx` part of an open-drain net: drivers can only pull it low, a driven 1 releases
+    /// it, and several drivers on the pin are wired-AND together
+    pub fn set_open_drain(&mut self, idx: usize, open_drain: bool) {
+        if open_drain {
+            self.open_drain |= self.pin_bit(idx);
+        } else {
+            self.open_drain &= !self.pin_bit(idx);
+        }
+        self.resolve();
+    }
+
+    /// Pins that nothing drives and that have neither an external input nor a pull
+    pub fn get_floating_vector(&self) -> u64 {
+        !self.driven & !self.external_enable & !(self.pull_up | self.pull_down) & self.pin_mask
+    }
+
+    /// Pins that currently have conflicting drivers
+    pub fn get_contention_vector(&self) -> u64 {
+        self.contended
+    }
+
+    /// Returns and clears the contentions recorded so far. A contention is recorded when a pin
+    /// starts being driven to different levels, not on every cycle it persists.
+    pub fn take_contentions(&mut self) -> Vec<Contention> {
+        std::mem::take(&mut self.contentions)
+    }
+
+    /// Schedules external input levels for `idx` as `(cycle, level)` pairs. Each level is applied
+    /// once `tick` reaches its cycle; cycles that have already passed are applied immediately.
+    pub fn add_stimulus(&mut self, idx: usize, waveform: &[(u64, u8)]) {
+        for (cycle, level) in waveform {
+            if *cycle <= self.cycle {
+                self.set_input_level(idx, *level);
+            } else {
+                self.stimulus.entry(*cycle).or_default().push((idx, *level));
+            }
+        }
+    }
+
+    pub fn clear_stimulus(&mut self) {
+        self.stimulus.clear();
+    }
+
+    pub fn get_cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Advances the GPIO clock by one cycle, applies any stimulus that has become due and
+    /// propagates the netlist. Called once per `PIO::step`.
+    pub fn tick(&mut self) {
+        self.cycle += 1;
+
+        while let Some(cycle) = self.stimulus.keys().next().copied() {
+            if cycle > self.cycle {
+                break;
+            }
+            for (idx, level) in self.stimulus.remove(&cycle).unwrap_or_default() {
+                self.set_input_level(idx, level);
+            }
+        }
+
+        let mut netlist = std::mem::take(&mut self.netlist);
+        netlist.propagate(self);
+        self.netlist = netlist;
+    }
+
+    /// Replaces the board wiring. Pins driven by the previous netlist are released.
+    pub fn set_netlist(&mut self, netlist: Netlist) -> Result<(), std::string::String> {
+        netlist.validate(self.pin_count)?;
+
+        for idx in 0..self.netlist.nets().len() {
+            self.drivers.remove(&GPIODriver::Net(idx as u32));
+        }
+        self.netlist = netlist;
+        self.resolve();
+        Ok(())
+    }
+
+    pub fn get_netlist(&self) -> &Netlist {
+        &self.netlist
+    }
+
+    /// Names a pin ("CLK") or a bus of consecutive pins starting at `base` ("DATA[7:0]")
+    pub fn name_pins(&mut self, spec: &str, base: usize) -> Result<(), std::string::String> {
+        let width = pin_names::spec_width(spec)?;
+        if base
+            .checked_add(width)
+            .is_none_or(|end| end > self.pin_count)
+        {
+            return Err(format!(
+                "{} uses missing GPIO{}",
+                spec,
+                base.max(self.pin_count)
+            ));
+        }
+        self.names.add(spec, base)
+    }
+
+    pub fn get_pin_names(&self) -> &PinNames {
+        &self.names
+    }
+
+    /// Name of `idx` for logs, "GPIOn" if it has not been named
+    pub fn pin_label(&self, idx: usize) -> String {
+        self.names.label(idx)
+    }
+
+    /// Labels of the pins set in `mask`, lowest first, for traces and errors
+    pub fn pin_labels(&self, mask: u64) -> String {
+        (0..MAX_PIN_COUNT)
+            .filter(|pin| mask & (1 << pin) != 0)
+            .map(|pin| self.pin_label(pin))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    /// Reads a named pin or bus as an integer, least significant bit first
+    pub fn read_bus(&self, spec: &str) -> Result<u64, std::string::String> {
+        let pins = self.names.lookup(spec)?;
+        Ok(pins.iter().enumerate().fold(0, |value, (bit, pin)| {
+            value | ((self.get_idx(*pin) as u64) << bit)
+        }))
+    }
+
+    /// Drives a named pin or bus from the board side, like `set_input_vector`
+    pub fn write_bus(&mut self, spec: &str, value: u64) -> Result<(), std::string::String> {
+        let pins = self.names.lookup(spec)?;
+        if pins.len() < 64 && value >> pins.len() != 0 {
+            return Err(format!("Value {:#x} does not fit in {}", value, spec));
+        }
+
+        let (value, mask) = pins
+            .iter()
+            .enumerate()
+            .fold((0, 0), |(levels, mask), (bit, pin)| {
+                (levels | (((value >> bit) & 1) << pin), mask | (1 << pin))
+            });
+        self.set_input_vector(value, mask);
+        Ok(())
+    }
+
+    /// Calls `callback` whenever one of the pins in the `pins` mask changes level in the
+    /// direction given by `edge`. The callback runs while the GPIO is locked, so it must not
+    /// call back into it.
+    pub fn subscribe<F>(&mut self, pins: u64, edge: Edge, callback: F) -> SubscriptionId
+    where
+        F: FnMut(PinEvent) + Send + 'static,
+    {
+        self.add_subscription(pins, edge, Listener::Callback(Box::new(callback)))
+    }
+
+    /// Like `subscribe`, but queues the events on a channel instead. The subscription is dropped
+    /// once the receiver is.
+    pub fn subscribe_channel(
+        &mut self,
+        pins: u64,
+        edge: Edge,
+    ) -> (SubscriptionId, mpsc::Receiver<PinEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        let id = self.add_subscription(pins, edge, Listener::Channel(sender));
+        (id, receiver)
     }
 
+    pub fn unsubscribe(&mut self, id: SubscriptionId) {
+        self.subscriptions
+            .retain(|subscription| subscription.id != id);
+    }
+
+    fn add_subscription(&mut self, pins: u64, edge: Edge, listener: Listener) -> SubscriptionId {
+        let id = SubscriptionId(self.next_subscription);
+        self.next_subscription += 1;
+        self.subscriptions.push(Subscription {
+            id,
+            pins,
+            edge,
+            listener,
+        });
+        id
+    }
+
+    fn notify(&mut self, changed: u64) {
+        let levels = self.levels;
+        let cycle = self.cycle;
+        self.subscriptions.retain_mut(|subscription| {
+            let mut pins = changed & subscription.pins;
+            while pins != 0 {
+                let pin = pins.trailing_zeros() as usize;
+                pins &= pins - 1;
+
+                let event = PinEvent {
+                    pin,
+                    level: ((levels >> pin) & 1) as u8,
+                    cycle,
+                };
+                if !subscription.edge.matches(event.level) {
+                    continue;
+                }
+                match &mut subscription.listener {
+                    Listener::Callback(callback) => callback(event),
+                    Listener::Channel(sender) => {
+                        if sender.send(event).is_err() {
+                            return false;
+                        }
+                    }
+                }
+            }
+            true
+        });
+    }
+
+    pub fn get_output_vector(&self) -> u64 {
+        self.resolved.value
+    }
+
+    pub fn get_enable_vector(&self) -> u64 {
+        self.resolved.enable
+    }
+
+    pub fn get_driver(&self, driver: GPIODriver) -> DriverState {
+        self.drivers.get(&driver).copied().unwrap_or_default()
+    }
+
+    pub fn drivers(&self) -> impl Iterator<Item = (&GPIODriver, &DriverState)> {
+        self.drivers.iter()
+    }
+
+    /// Combined contribution of every state machine in one PIO block, as seen by `DBG_PADOUT`
+    /// and `DBG_PADOE`
+    pub fn get_pio_outputs(&self, pio: u8) -> DriverState {
+        self.drivers
+            .iter()
+            .filter(|(driver, _)| driver.pio_block() == Some(pio))
+            .fold(DriverState::default(), |combined, (_, state)| {
+                combined.merge(*state)
+            })
+    }
+
+    /// Updates the output value of `driver` for the pins set in `mask`
+    pub fn set_driver_value(&mut self, driver: GPIODriver, value: u64, mask: u64) {
+        let mask = mask & self.pin_mask;
+        let state = self.drivers.entry(driver).or_default();
+        state.value = (state.value & !mask) | (value & mask);
+        self.resolve();
+    }
+
+    /// Updates the output enable of `driver` for the pins set in `mask`
+    pub fn set_driver_enable(&mut self, driver: GPIODriver, enable: u64, mask: u64) {
+        let mask = mask & self.pin_mask;
+        let state = self.drivers.entry(driver).or_default();
+        state.enable = (state.enable & !mask) | (enable & mask);
+        self.resolve();
+    }
+
+    /// Removes every contribution of `driver`
+    pub fn release_driver(&mut self, driver: GPIODriver) {
+        self.drivers.remove(&driver);
+        self.resolve();
+    }
+
+    /// Drives `count` pins starting at `base` within the 32 pin window of a PIO that starts at
+    /// physical pin `window_base`
     pub fn set_idx_continuous(
         &mut self,
+        driver: GPIODriver,
+        window_base: u32,
         base: u32,
         count: u32,
         value: u8,
     ) -> Result<(), std::string::String> {
-        for x in 0..count {
-            let current_bit = (value >> x) & 1;
-            let current_idx = (base + x) % 32;
-
-            self.inner[current_idx as usize] = current_bit;
-        }
+        let (value, mask) = Self::pin_window(window_base, base, count, value as u32)?;
+        self.set_driver_value(driver, value, mask);
         Ok(())
     }
 
     pub fn set_idx_enable_continuous(
         &mut self,
+        driver: GPIODriver,
+        window_base: u32,
         base: u32,
         count: u32,
         value: u8,
     ) -> Result<(), std::string::String> {
-        for x in 0..count {
-            let current_bit = (value >> x) & 1;
-            let current_idx = (base + x) % 32;
+        let (value, mask) = Self::pin_window(window_base, base, count, value as u32)?;
+        self.set_driver_enable(driver, value, mask);
+        Ok(())
+    }
 
-            self.inner_enable[current_idx as usize] = current_bit;
+    /// Places the low `count` bits of `value` at `base`, wrapping around the 32 pin window the
+    /// way the PIO pin mapping does, and moves the window to `window_base`
+    fn pin_window(
+        window_base: u32,
+        base: u32,
+        count: u32,
+        value: u32,
+    ) -> Result<(u64, u64), std::string::String> {
+        if count > 32 {
+            return Err(format!("Invalid pin count : {}", count));
         }
-        Ok(())
+        if window_base > 32 {
+            return Err(format!("Invalid PIO window base : {}", window_base));
+        }
+
+        let count_mask = ((1u64 << count) - 1) as u32;
+        Ok((
+            ((value & count_mask).rotate_left(base % 32) as u64) << window_base,
+            (count_mask.rotate_left(base % 32) as u64) << window_base,
+        ))
+    }
+
+    /// Pins whose FUNCSEL connects them to `driver`
+    fn driver_reach(&self, driver: GPIODriver) -> u64 {
+        match driver.function() {
+            Some(function) => self
+                .function_pins
+                .get(&(function as u8))
+                .copied()
+                .unwrap_or(0),
+            None => !0,
+        }
+    }
+
+    fn resolve(&mut self) {
+        // Contention is judged on what the drivers request, before the pad overrides
+        let mut driven_high = 0;
+        let mut driven_low = 0;
+        let mut chip = DriverState::default();
+        let mut chip_low = 0;
+        let mut external = DriverState::default();
+        let mut external_low = 0;
+        for (driver, state) in self.drivers.iter() {
+            let state = DriverState {
+                value: state.value,
+                enable: state.enable & self.driver_reach(*driver),
+            };
+            driven_high |= state.enable & state.value;
+            driven_low |= state.enable & !state.value;
+            if driver.function().is_some() {
+                chip = chip.merge(state);
+                chip_low |= state.enable & !state.value;
+            } else {
+                external = external.merge(state);
+                external_low |= state.enable & !state.value;
+            }
+        }
+
+        let chip = DriverState {
+            value: self.output_override.apply(chip.value),
+            enable: self.enable_override.apply(chip.enable),
+        };
+        self.resolved = chip.merge(external);
+
+        // Open-drain pins are only ever driven low, by any of the drivers reaching them rather
+        // than just the winning one. Push-pull pins follow the winning driver.
+        let wired_and = self.output_override.apply(!chip_low);
+        let push_pull = self.resolved.enable & !self.open_drain;
+        let pulled_low = ((chip.enable & !wired_and) | external_low) & self.open_drain;
+        self.driven = push_pull | pulled_low;
+        let driven_levels = self.resolved.value & push_pull;
+
+        let bus_keeper = self.pull_up & self.pull_down;
+        let pulled = (self.pull_up & !self.pull_down) | (bus_keeper & self.levels);
+        let undriven_levels =
+            (self.external & self.external_enable) | (pulled & !self.external_enable);
+
+        let previous_levels = self.levels;
+        self.levels = driven_levels | (undriven_levels & !self.driven);
+        if previous_levels != self.levels {
+            self.notify(previous_levels ^ self.levels);
+        }
+
+        let contended = driven_high & driven_low & !self.open_drain;
+        let new_contentions = contended & !self.contended;
+        self.contended = contended;
+        for pin in (0..self.pin_count).filter(|pin| new_contentions & (1 << pin) != 0) {
+            let drivers = self
+                .drivers
+                .iter()
+                .filter(|(driver, state)| {
+                    state.enable & self.driver_reach(**driver) & (1 << pin) != 0
+                })
+                .map(|(driver, _)| *driver)
+                .collect();
+            self.contentions.push(Contention {
+                cycle: self.cycle,
+                pin,
+                label: self.names.label(pin),
+                drivers,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SM0: GPIODriver = GPIODriver::StateMachine { pio: 0, sm: 0 };
+    const SM1: GPIODriver = GPIODriver::StateMachine { pio: 0, sm: 1 };
+    const PIO0_CONTROL: PinControl = PinControl {
+        function: FunctionSelect::PIO0,
+        output_override: Override::Normal,
+        enable_override: Override::Normal,
+        input_override: Override::Normal,
+    };
+
+    #[test]
+    fn drivers_keep_separate_masks_and_higher_state_machines_win() {
+        let mut gpio = GPIO::new();
+        gpio.set_driver_value(SM0, 0b0011, 0b0011);
+        gpio.set_driver_enable(SM0, 0b0011, 0b1111);
+        gpio.set_driver_value(SM1, 0b0000, 0b0010);
+        gpio.set_driver_enable(SM1, 0b0110, 0b0110);
+
+        assert_eq!(
+            gpio.get_driver(SM0),
+            DriverState {
+                value: 0b0011,
+                enable: 0b0011
+            }
+        );
+        assert_eq!(gpio.get_enable_vector(), 0b0111);
+        assert_eq!(gpio.get_output_vector(), 0b0001);
+        assert_eq!(gpio.get_level_vector(), 0b0001);
+        assert_eq!(
+            gpio.get_pio_outputs(0),
+            DriverState {
+                value: 0b0001,
+                enable: 0b0111
+            }
+        );
+        assert_eq!(gpio.get_pio_outputs(1), DriverState::default());
+
+        gpio.release_driver(SM1);
+        assert_eq!(gpio.get_output_vector(), 0b0011);
+        assert_eq!(gpio.get_enable_vector(), 0b0011);
+        assert_eq!(gpio.drivers().count(), 1);
+    }
+
+    #[test]
+    fn open_drain_pins_are_wired_and_across_drivers() {
+        let mut gpio = GPIO::new();
+        gpio.set_open_drain(3, true);
+        gpio.set_pull(3, Pull::Up);
+        gpio.set_driver_enable(SM0, 1 << 3, 1 << 3);
+        gpio.set_driver_enable(SM1, 1 << 3, 1 << 3);
+        gpio.set_driver_value(SM1, 1 << 3, 1 << 3);
+
+        assert_eq!(gpio.get_idx(3), 0);
+        assert_eq!(gpio.get_floating_vector() & (1 << 3), 0);
+
+        gpio.set_driver_value(SM0, 1 << 3, 1 << 3);
+        assert_eq!(gpio.get_idx(3), 1);
+
+        gpio.set_driver_value(SM1, 0, 1 << 3);
+        assert_eq!(gpio.get_idx(3), 0);
+        assert!(gpio.take_contentions().is_empty());
+    }
+
+    #[test]
+    fn undriven_pins_follow_inputs_then_pulls() {
+        let mut gpio = GPIO::new();
+        assert_eq!(gpio.get_floating_vector() & 0b110000, 0b110000);
+
+        gpio.set_pull(4, Pull::Up);
+        assert_eq!(gpio.get_idx(4), 1);
+        assert_eq!(gpio.get_pull(4), Pull::Up);
+        gpio.set_input_level(4, 0);
+        assert_eq!(gpio.get_idx(4), 0);
+        gpio.release_input(4);
+        assert_eq!(gpio.get_idx(4), 1);
+        gpio.set_pull(4, Pull::Down);
+        assert_eq!(gpio.get_idx(4), 0);
+
+        // A bus keeper holds whatever level the pin was last driven to
+        gpio.set_driver_value(SM0, 1 << 5, 1 << 5);
+        gpio.set_driver_enable(SM0, 1 << 5, 1 << 5);
+        gpio.set_pull(5, Pull::BusKeeper);
+        gpio.release_driver(SM0);
+        assert_eq!(gpio.get_idx(5), 1);
+        assert_eq!(gpio.get_floating_vector() & 0b110000, 0);
+    }
+
+    #[test]
+    fn contention_names_only_drivers_that_reach_the_pin() {
+        let mut gpio = GPIO::new();
+        gpio.set_driver_value(SM0, 1, 1);
+        gpio.set_driver_enable(SM0, 1, 1);
+        gpio.set_driver_enable(SM1, 1, 1);
+        // SIO does not reach GPIO0 while its FUNCSEL selects PIO0
+        gpio.set_driver_enable(GPIODriver::CPU, 1, 1);
+
+        assert_eq!(gpio.get_contention_vector(), 1);
+        let contentions = gpio.take_contentions();
+        assert_eq!(contentions.len(), 1);
+        assert_eq!(contentions[0].pin, 0);
+        assert_eq!(contentions[0].drivers, vec![SM0, SM1]);
+        assert_eq!(
+            contentions[0].to_string(),
+            "cycle 0 : contention on GPIO0 between [StateMachine { pio: 0, sm: 0 }, StateMachine { pio: 0, sm: 1 }]"
+        );
+
+        // Only the start of a contention is recorded
+        gpio.tick();
+        gpio.set_driver_value(SM1, 2, 2);
+        assert!(gpio.take_contentions().is_empty());
+
+        gpio.set_driver_value(SM1, 1, 1);
+        assert_eq!(gpio.get_contention_vector(), 0);
+    }
+
+    #[test]
+    fn funcsel_picks_the_driver_that_reaches_a_pin() {
+        let mut gpio = GPIO::new();
+        gpio.set_driver_value(SM0, 0b11, 0b11);
+        gpio.set_driver_enable(SM0, 0b11, 0b11);
+        gpio.set_driver_enable(GPIODriver::CPU, 0b11, 0b11);
+
+        gpio.set_function(1, FunctionSelect::SIO);
+        assert_eq!(gpio.get_pin_control(1).function, FunctionSelect::SIO);
+        assert_eq!(gpio.get_level_vector(), 0b01);
+        assert_eq!(gpio.get_contention_vector(), 0);
+
+        gpio.set_function(0, FunctionSelect::NULL);
+        assert_eq!(gpio.get_enable_vector(), 0b10);
+        assert_eq!(gpio.get_idx_enable(0), 0);
+    }
+
+    #[test]
+    fn overrides_apply_between_peripherals_and_pad() {
+        let mut gpio = GPIO::new();
+        gpio.set_driver_value(SM0, 0b111, 0b111);
+        gpio.set_driver_enable(SM0, 0b111, 0b111);
+        gpio.set_pin_control(
+            0,
+            PinControl {
+                output_override: Override::Invert,
+                ..PIO0_CONTROL
+            },
+        );
+        gpio.set_pin_control(
+            1,
+            PinControl {
+                enable_override: Override::Low,
+                ..PIO0_CONTROL
+            },
+        );
+        gpio.set_pin_control(
+            2,
+            PinControl {
+                input_override: Override::Low,
+                ..PIO0_CONTROL
+            },
+        );
+
+        assert_eq!(gpio.get_level_vector() & 0b111, 0b100);
+        assert_eq!(gpio.get_enable_vector(), 0b101);
+        assert_eq!(gpio.get_peripheral_input_vector() & 0b111, 0b000);
+        // The requested levels are unchanged, only what reaches the pad
+        assert_eq!(gpio.get_driver(SM0).value, 0b111);
+
+        gpio.set_pin_control(0, PIO0_CONTROL);
+        assert_eq!(gpio.get_idx(0), 1);
+    }
+
+    #[test]
+    fn pins_past_the_pin_count_read_zero_and_ignore_writes() {
+        assert!(GPIO::with_pin_count(65, FunctionSelect::PIO0).is_err());
+
+        let mut gpio = GPIO::with_pin_count(RP2040_PIN_COUNT, FunctionSelect::PIO0).unwrap();
+        gpio.set_driver_value(SM0, !0, !0);
+        gpio.set_driver_enable(SM0, !0, !0);
+        gpio.set_input_level(31, 1);
+        gpio.set_function(31, FunctionSelect::SIO);
+
+        assert_eq!(gpio.get_pin_count(), 30);
+        assert_eq!(gpio.get_level_vector(), (1 << 30) - 1);
+        assert_eq!(gpio.get_idx(30), 0);
+        assert_eq!(gpio.get_idx(64), 0);
+        assert_eq!(gpio.get_input_vector(), 0);
+        assert_eq!(gpio.get_pin_control(31), PinControl::default());
+    }
+
+    #[test]
+    fn pio_window_wraps_within_its_32_pins() {
+        let mut gpio = GPIO::with_pin_count(RP2350B_PIN_COUNT, FunctionSelect::PIO0).unwrap();
+        gpio.set_idx_enable_continuous(SM0, 16, 30, 4, 0b1111)
+            .unwrap();
+        gpio.set_idx_continuous(SM0, 16, 30, 4, 0b0101).unwrap();
+
+        let pins = (0b11 << 46) | (0b11 << 16);
+        assert_eq!(gpio.get_enable_vector(), pins);
+        assert_eq!(gpio.get_level_vector(), (1 << 46) | (1 << 16));
+
+        assert!(gpio.set_idx_continuous(SM0, 33, 0, 1, 1).is_err());
+        assert!(gpio.set_idx_continuous(SM0, 0, 0, 33, 1).is_err());
+    }
+
+    #[test]
+    fn subscriptions_see_matching_edges_with_their_cycle() {
+        let mut gpio = GPIO::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let rising = events.clone();
+        let id = gpio.subscribe(0b01, Edge::Rising, move |event| {
+            rising.lock().unwrap().push(event)
+        });
+        let (_, receiver) = gpio.subscribe_channel(0b11, Edge::Any);
+
+        gpio.add_stimulus(0, &[(2, 1), (3, 0)]);
+        gpio.add_stimulus(1, &[(3, 1)]);
+        for _ in 0..3 {
+            gpio.tick();
+        }
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![PinEvent {
+                pin: 0,
+                level: 1,
+                cycle: 2
+            }]
+        );
+        let any: Vec<PinEvent> = receiver.try_iter().collect();
+        assert_eq!(
+            any,
+            vec![
+                PinEvent {
+                    pin: 0,
+                    level: 1,
+                    cycle: 2
+                },
+                PinEvent {
+                    pin: 0,
+                    level: 0,
+                    cycle: 3
+                },
+                PinEvent {
+                    pin: 1,
+                    level: 1,
+                    cycle: 3
+                },
+            ]
+        );
+
+        gpio.unsubscribe(id);
+        drop(receiver);
+        gpio.set_input_level(0, 1);
+        assert_eq!(events.lock().unwrap().len(), 1);
+        assert!(gpio.subscriptions.is_empty());
+    }
+
+    #[test]
+    fn named_buses_read_write_and_label_pins() {
+        let mut gpio = GPIO::with_pin_count(RP2040_PIN_COUNT, FunctionSelect::PIO0).unwrap();
+        gpio.name_pins("DATA[3:0]", 4).unwrap();
+        gpio.name_pins("CLK", 0).unwrap();
+        assert_eq!(
+            gpio.name_pins("HIGH[3:0]", 28).unwrap_err(),
+            "HIGH[3:0] uses missing GPIO30"
+        );
+        assert_eq!(
+            gpio.name_pins("HUGE[4294967295:0]", 0).unwrap_err(),
+            "HUGE[4294967295:0] is wider than 64 pins"
+        );
+        assert!(gpio.name_pins("FAR", usize::MAX).is_err());
+
+        gpio.write_bus("DATA", 0xA).unwrap();
+        assert_eq!(gpio.get_level_vector(), 0xA << 4);
+        assert_eq!(gpio.read_bus("DATA[3:1]").unwrap(), 0x5);
+        assert!(gpio.write_bus("DATA", 0x10).is_err());
+        assert!(gpio.read_bus("ADDR").is_err());
+
+        gpio.set_driver_value(SM0, 0, 1 << 5);
+        gpio.set_driver_enable(SM0, 1 << 5, 1 << 5);
+        gpio.set_driver_enable(SM1, 1 << 5, 1 << 5);
+        gpio.set_driver_value(SM1, 1 << 5, 1 << 5);
+        assert_eq!(gpio.take_contentions()[0].label, "DATA[1]");
+        assert_eq!(gpio.pin_label(20), "GPIO20");
+
+        let dump = gpio.to_string();
+        assert!(dump.starts_with("DATA[3:0]        0xA\nCLK              0x0\n"));
+        assert!(dump.contains("GPIO5  DATA[1]          out 1\n"));
     }
 }
@@ -1,6 +1,9 @@
 use std::convert::TryFrom;
+use std::fmt;
 
-#[derive(Debug)]
+use crate::memory_backing::IMEM_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PIOInstruction {
     JMP {
         delay_sideset: u8,
@@ -54,8 +57,6 @@ pub enum PIOInstruction {
 
 impl PIOInstruction {
     pub fn decode(instr_data: u32) -> Result<Self, std::string::String> {
-        println!("Decoding : 0x{:X}", instr_data);
-
         let op_code = (instr_data >> 13) & 7;
         let delay_sideset = ((instr_data >> 8) & 0x1f) as u8;
 
@@ -151,10 +152,790 @@ impl PIOInstruction {
             _ => Err(format!("Invalid instruction op code : {}", op_code)),
         }
     }
+
+    /// Encodes the instruction back into a word, with `delay_sideset` copied into the delay
+    /// field as is. Reserved operands and out of range fields are errors.
+    pub fn encode(&self) -> Result<u16, std::string::String> {
+        if self.operation_text().is_none() {
+            return Err(format!("Cannot encode reserved instruction : {:?}", self));
+        }
+
+        let (op_code, arguments, low): (u16, u8, u8) = match *self {
+            Self::JMP {
+                condition, address, ..
+            } => (0, condition as u8, address),
+            Self::WAIT {
+                polarity,
+                source,
+                index,
+                ..
+            } => (1, (polarity as u8) << 2 | source as u8, index),
+            Self::IN {
+                source, bit_count, ..
+            } => (2, source as u8, bit_count),
+            Self::OUT {
+                destination,
+                bit_count,
+                ..
+            } => (3, destination as u8, bit_count),
+            Self::PUSH { if_full, block, .. } => (4, (if_full as u8) << 1 | block as u8, 0),
+            Self::PULL {
+                if_empty, block, ..
+            } => (4, 4 | (if_empty as u8) << 1 | block as u8, 0),
+            Self::MOV {
+                destination,
+                op,
+                source,
+                ..
+            } => (5, destination as u8, (op as u8) << 3 | source as u8),
+            Self::IRQ {
+                clear, wait, index, ..
+            } => (6, (clear as u8) << 1 | wait as u8, index),
+            Self::SET {
+                destination, data, ..
+            } => (7, destination as u8, data),
+        };
+
+        let delay_sideset = self.delay_sideset();
+        if delay_sideset > 0x1f {
+            return Err(format!(
+                "Delay/side-set field out of range : {}",
+                delay_sideset
+            ));
+        }
+        if low > 0x1f {
+            return Err(format!("Operand out of range : {:?}", self));
+        }
+
+        Ok(op_code << 13 | (delay_sideset as u16) << 8 | (arguments as u16) << 5 | low as u16)
+    }
+
+    /// Encodes the instruction with its delay/side-set field packed from `side` and `delay`
+    /// for the given side-set configuration, replacing whatever `delay_sideset` holds
+    pub fn encode_with_side_set(
+        &self,
+        side_set: &SideSet,
+        side: Option<u8>,
+        delay: u8,
+    ) -> Result<u16, std::string::String> {
+        if side_set.bits() > 5 {
+            return Err(format!("Side-set of {} bits does not fit", side_set.bits()));
+        }
+        match side {
+            Some(_) if side_set.count == 0 => {
+                return Err("Side-set value given without a side-set configured".to_string())
+            }
+            Some(value) if value > side_set.max_value() => {
+                return Err(format!(
+                    "Side-set value {} does not fit in {} bits",
+                    value, side_set.count
+                ))
+            }
+            None if side_set.count != 0 && !side_set.optional => {
+                return Err("Side-set value is required by the side-set configuration".to_string())
+            }
+            _ => (),
+        }
+        if delay > side_set.max_delay() {
+            return Err(format!(
+                "Delay {} is larger than the maximum of {}",
+                delay,
+                side_set.max_delay()
+            ));
+        }
+
+        let word = self.encode()?;
+        Ok(word & !0x1f00 | (side_set.pack(side, delay) as u16) << 8)
+    }
+
+    /// Whether the instruction uses an encoding the hardware reserves
+    pub fn is_reserved(&self) -> bool {
+        self.operation_text().is_none()
+    }
+
+    fn delay_sideset(&self) -> u8 {
+        match self {
+            Self::JMP { delay_sideset, .. }
+            | Self::WAIT { delay_sideset, .. }
+            | Self::IN { delay_sideset, .. }
+            | Self::OUT { delay_sideset, .. }
+            | Self::PUSH { delay_sideset, .. }
+            | Self::PULL { delay_sideset, .. }
+            | Self::MOV { delay_sideset, .. }
+            | Self::IRQ { delay_sideset, .. }
+            | Self::SET { delay_sideset, .. } => *delay_sideset,
+        }
+    }
+
+    /// Mnemonic and operands, `None` for reserved encodings
+    fn operation_text(&self) -> Option<(&'static str, std::string::String)> {
+        let text = match self {
+            Self::JMP {
+                condition, address, ..
+            } => {
+                let condition = match condition {
+                    JmpCondition::Always => "",
+                    JmpCondition::ScratchXZero => "!x, ",
+                    JmpCondition::ScratchXNonZeroPostDecrement => "x--, ",
+                    JmpCondition::ScratchYZero => "!y, ",
+                    JmpCondition::ScratchYNonZeroPostDecrement => "y--, ",
+                    JmpCondition::ScratchXNotEqualScratchY => "x != y, ",
+                    JmpCondition::BranchOnInputPin => "pin, ",
+                    JmpCondition::OutputShiftRegisterNotEmpty => "!osre, ",
+                };
+                ("jmp", format!("{}{}", condition, address))
+            }
+            Self::WAIT {
+                polarity,
+                source,
+                index,
+                ..
+            } => {
+                let polarity = match polarity {
+                    WaitPolarity::Zero => 0,
+                    WaitPolarity::One => 1,
+                };
+                let source = match source {
+                    WaitSource::GPIO => format!("gpio, {}", index),
+                    WaitSource::Pin => format!("pin, {}", index),
+                    WaitSource::IRQ if index & 0x8 != 0 => return None,
+                    WaitSource::IRQ if index & 0x10 != 0 => format!("irq, {} rel", index & 7),
+                    WaitSource::IRQ => format!("irq, {}", index & 7),
+                    WaitSource::Reserved => return None,
+                };
+                ("wait", format!("{} {}", polarity, source))
+            }
+            Self::IN {
+                source, bit_count, ..
+            } => {
+                let source = match source {
+                    InSource::PINS => "pins",
+                    InSource::X => "x",
+                    InSource::Y => "y",
+                    InSource::NULL => "null",
+                    InSource::ISR => "isr",
+                    InSource::OSR => "osr",
+                    InSource::Reserved0 | InSource::Reserved1 => return None,
+                };
+                ("in", format!("{}, {}", source, bit_count_text(*bit_count)))
+            }
+            Self::OUT {
+                destination,
+                bit_count,
+                ..
+            } => {
+                let destination = match destination {
+                    OutDestination::PINS => "pins",
+                    OutDestination::X => "x",
+                    OutDestination::Y => "y",
+                    OutDestination::NULL => "null",
+                    OutDestination::PINDIRS => "pindirs",
+                    OutDestination::PC => "pc",
+                    OutDestination::ISR => "isr",
+                    OutDestination::EXEC => "exec",
+                };
+                (
+                    "out",
+                    format!("{}, {}", destination, bit_count_text(*bit_count)),
+                )
+            }
+            Self::PUSH { if_full, block, .. } => {
+                let if_full = if *if_full { "iffull " } else { "" };
+                let block = if *block { "block" } else { "noblock" };
+                ("push", format!("{}{}", if_full, block))
+            }
+            Self::PULL {
+                if_empty, block, ..
+            } => {
+                let if_empty = if *if_empty { "ifempty " } else { "" };
+                let block = if *block { "block" } else { "noblock" };
+                ("pull", format!("{}{}", if_empty, block))
+            }
+            Self::MOV {
+                destination,
+                op,
+                source,
+                ..
+            } => {
+                let destination = match destination {
+                    MovDestination::PINS => "pins",
+                    MovDestination::X => "x",
+                    MovDestination::Y => "y",
+                    MovDestination::EXEC => "exec",
+                    MovDestination::PC => "pc",
+                    MovDestination::ISR => "isr",
+                    MovDestination::OSR => "osr",
+                    MovDestination::Reserved => return None,
+                };
+                // Source 7 is the OSR for MOV, the enum is named after OUT's EXEC
+                let source = match source {
+                    MovSource::PINS => "pins",
+                    MovSource::X => "x",
+                    MovSource::Y => "y",
+                    MovSource::NULL => "null",
+                    MovSource::STATUS => "status",
+                    MovSource::ISR => "isr",
+                    MovSource::EXEC => "osr",
+                    MovSource::Reserved => return None,
+                };
+                let op = match op {
+                    MovOp::None => "",
+                    MovOp::Invert => "!",
+                    MovOp::BitReverse => "::",
+                    MovOp::Reserved => return None,
+                };
+                // pioasm spells "mov x, x" and "mov y, y" as nop
+                if destination == source
+                    && (destination == "x" || destination == "y")
+                    && op.is_empty()
+                {
+                    ("nop", std::string::String::new())
+                } else {
+                    ("mov", format!("{}, {}{}", destination, op, source))
+                }
+            }
+            Self::IRQ {
+                clear, wait, index, ..
+            } => {
+                if index & 0x8 != 0 {
+                    return None;
+                }
+                let mode = if *clear {
+                    "clear"
+                } else if *wait {
+                    "wait"
+                } else {
+                    "nowait"
+                };
+                let relative = if index & 0x10 != 0 { " rel" } else { "" };
+                ("irq", format!("{} {}{}", mode, index & 7, relative))
+            }
+            Self::SET {
+                destination, data, ..
+            } => {
+                let destination = match destination {
+                    SetDestination::PINS => "pins",
+                    SetDestination::X => "x",
+                    SetDestination::Y => "y",
+                    SetDestination::PINDIRS => "pindirs",
+                    SetDestination::Reserved0
+                    | SetDestination::Reserved1
+                    | SetDestination::Reserved2
+                    | SetDestination::Reserved3 => return None,
+                };
+                ("set", format!("{}, {}", destination, data))
+            }
+        };
+        Some(text)
+    }
+
+    /// Renders the instruction the way pioasm's disassembler does, in fixed width columns for
+    /// the mnemonic, operands, side-set and delay. Reserved encodings render as "reserved".
+    pub fn disassemble(&self, side_set: &SideSet) -> std::string::String {
+        let (mnemonic, operands) = match self.operation_text() {
+            Some(text) => text,
+            None => return "reserved".to_string(),
+        };
+
+        let field = self.delay_sideset();
+        let bits = side_set.bits();
+        let side = if bits != 0 && (!side_set.optional || field & 0x10 != 0) {
+            let value_mask = if side_set.optional { 0xf } else { 0x1f };
+            format!("side {}", (field & value_mask) >> (5 - bits))
+        } else {
+            std::string::String::new()
+        };
+        let delay = field & side_set.max_delay();
+        let delay = if delay != 0 {
+            format!("[{}]", delay)
+        } else {
+            std::string::String::new()
+        };
+
+        format!("{:<7}{:<16}{:<7}{:<4}", mnemonic, operands, side, delay)
+    }
+}
+
+fn bit_count_text(bit_count: u8) -> u8 {
+    if bit_count == 0 {
+        32
+    } else {
+        bit_count
+    }
+}
+
+/// Whether a raw instruction word is reserved, including the reserved bits `decode` does not keep
+pub fn is_reserved_word(word: u16) -> bool {
+    let reserved_bits = match word >> 13 {
+        // PUSH and PULL with any of the low 5 bits set
+        4 => word & 0x1f != 0,
+        // IRQ with bit 7 set
+        6 => word & 0x80 != 0,
+        _ => false,
+    };
+    reserved_bits
+        || PIOInstruction::decode(word as u32).map_or(true, |instruction| instruction.is_reserved())
+}
+
+/// Disassembles a raw instruction word, see `PIOInstruction::disassemble`
+pub fn disassemble_word(word: u16, side_set: &SideSet) -> std::string::String {
+    match PIOInstruction::decode(word as u32) {
+        Ok(instruction) if !is_reserved_word(word) => instruction.disassemble(side_set),
+        _ => "reserved".to_string(),
+    }
+}
+
+/// Canonical pioasm text assuming no side-set, so the whole delay/side-set field is a delay.
+/// Use `disassemble` when the state machine has side-set pins configured.
+impl fmt::Display for PIOInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.disassemble(&SideSet::default()).trim_end())
+    }
+}
+
+/// How the 5 bit delay/side-set field of every instruction is split, as set by `.side_set`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SideSet {
+    /// Side-set data bits, not counting the enable bit of an optional side-set
+    pub count: u8,
+    pub optional: bool,
+    pub pindirs: bool,
+}
+
+impl SideSet {
+    /// Bits taken from the delay field, the value SM_PINCTRL.SIDESET_COUNT needs
+    pub fn bits(&self) -> u8 {
+        self.count + self.optional as u8
+    }
+
+    pub fn max_delay(&self) -> u8 {
+        (1 << (5 - self.bits())) - 1
+    }
+
+    pub fn max_value(&self) -> u8 {
+        ((1u16 << self.count) - 1) as u8
+    }
+
+    /// Packs a side-set value and delay into the 5 bit field
+    pub fn pack(&self, side: Option<u8>, delay: u8) -> u8 {
+        let side = match side {
+            Some(value) => {
+                let enable = if self.optional { 0x10 } else { 0 };
+                enable | (value << (5 - self.bits()))
+            }
+            None => 0,
+        };
+        side | delay
+    }
+}
+
+/// A named value from `.define` or a label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub value: i64,
+    pub is_label: bool,
+    pub public: bool,
+}
+
+/// Output language specific setting from `.lang_opt`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LangOpt {
+    pub lang: String,
+    pub name: String,
+    pub value: String,
+}
+
+/// An assembled PIO program together with the state machine configuration it expects. Addresses
+/// are relative to the start of the program.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    pub name: String,
+    pub instructions: Vec<u16>,
+    /// Fixed load address from `.origin`
+    pub origin: Option<u8>,
+    pub wrap_target: u8,
+    pub wrap: u8,
+    pub side_set: SideSet,
+    /// Symbols in definition order
+    pub symbols: Vec<Symbol>,
+    pub lang_opts: Vec<LangOpt>,
+    /// Verbatim `% lang { ... %}` blocks as (lang, text)
+    pub code_blocks: Vec<(String, String)>,
+}
+
+impl Program {
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    pub fn builder() -> ProgramBuilder {
+        ProgramBuilder::new()
+    }
+
+    pub fn public_symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(|symbol| symbol.public)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BuilderInstruction {
+    instruction: PIOInstruction,
+    /// Label a JMP branches to, resolved by `build`
+    target: Option<String>,
+    side: Option<u8>,
+    delay: u8,
+}
+
+/// Builds a `Program` from typed instructions instead of pioasm text. Calls chain, `side` and
+/// `delay` apply to the instruction added last, and every error is reported by `build`.
+#[derive(Debug, Clone)]
+pub struct ProgramBuilder {
+    name: String,
+    side_set: SideSet,
+    origin: Option<u8>,
+    wrap_target: Option<usize>,
+    wrap: Option<usize>,
+    labels: Vec<(String, usize)>,
+    instructions: Vec<BuilderInstruction>,
+    /// First misuse of the builder, such as `side` before any instruction
+    error: Option<String>,
+}
+
+impl Default for ProgramBuilder {
+    fn default() -> Self {
+        ProgramBuilder {
+            name: "program".to_string(),
+            side_set: SideSet::default(),
+            origin: None,
+            wrap_target: None,
+            wrap: None,
+            labels: Vec::new(),
+            instructions: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        ProgramBuilder::default()
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Same as `.side_set count [opt]`, `count` not including the enable bit
+    pub fn side_set(mut self, count: u8, optional: bool) -> Self {
+        self.side_set.count = count;
+        self.side_set.optional = optional;
+        self
+    }
+
+    /// Side-set drives pin directions instead of pin values, like `.side_set ... pindirs`
+    pub fn side_set_pindirs(mut self) -> Self {
+        self.side_set.pindirs = true;
+        self
+    }
+
+    pub fn origin(mut self, origin: u8) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    /// Labels the next instruction
+    pub fn label(mut self, name: &str) -> Self {
+        if self.labels.iter().any(|(label, _)| label == name) {
+            self.fail(format!("Duplicate label : {}", name));
+        }
+        self.labels
+            .push((name.to_string(), self.instructions.len()));
+        self
+    }
+
+    /// Wraps back to the next instruction
+    pub fn wrap_target(mut self) -> Self {
+        if self.wrap_target.is_some() {
+            self.fail("Duplicate wrap_target".to_string());
+        }
+        self.wrap_target = Some(self.instructions.len());
+        self
+    }
+
+    /// Wraps after the instruction added last
+    pub fn wrap(mut self) -> Self {
+        if self.wrap.is_some() {
+            self.fail("Duplicate wrap".to_string());
+        } else if self.instructions.is_empty() {
+            self.fail("wrap must follow an instruction".to_string());
+        }
+        self.wrap = Some(self.instructions.len().saturating_sub(1));
+        self
+    }
+
+    /// Side-set value of the instruction added last
+    pub fn side(mut self, value: u8) -> Self {
+        match self.instructions.last_mut() {
+            Some(last) => last.side = Some(value),
+            None => self.fail("side must follow an instruction".to_string()),
+        }
+        self
+    }
+
+    /// Delay cycles of the instruction added last
+    pub fn delay(mut self, cycles: u8) -> Self {
+        match self.instructions.last_mut() {
+            Some(last) => last.delay = cycles,
+            None => self.fail("delay must follow an instruction".to_string()),
+        }
+        self
+    }
+
+    /// Jumps to a label, which may be defined later
+    pub fn jmp(mut self, condition: JmpCondition, label: &str) -> Self {
+        self.add(
+            PIOInstruction::JMP {
+                delay_sideset: 0,
+                condition,
+                address: 0,
+            },
+            Some(label.to_string()),
+        );
+        self
+    }
+
+    /// Jumps to an address relative to the start of the program
+    pub fn jmp_address(mut self, condition: JmpCondition, address: u8) -> Self {
+        self.add(
+            PIOInstruction::JMP {
+                delay_sideset: 0,
+                condition,
+                address,
+            },
+            None,
+        );
+        self
+    }
+
+    /// `index` includes the relative flag 0x10 for IRQ waits
+    pub fn wait(mut self, polarity: WaitPolarity, source: WaitSource, index: u8) -> Self {
+        self.add(
+            PIOInstruction::WAIT {
+                delay_sideset: 0,
+                polarity,
+                source,
+                index,
+            },
+            None,
+        );
+        self
+    }
+
+    /// `bit_count` runs from 1 to 32
+    pub fn in_(mut self, source: InSource, bit_count: u8) -> Self {
+        let bit_count = self.bit_count(bit_count);
+        self.add(
+            PIOInstruction::IN {
+                delay_sideset: 0,
+                source,
+                bit_count,
+            },
+            None,
+        );
+        self
+    }
+
+    /// `bit_count` runs from 1 to 32
+    pub fn out(mut self, destination: OutDestination, bit_count: u8) -> Self {
+        let bit_count = self.bit_count(bit_count);
+        self.add(
+            PIOInstruction::OUT {
+                delay_sideset: 0,
+                destination,
+                bit_count,
+            },
+            None,
+        );
+        self
+    }
+
+    pub fn push(mut self, if_full: bool, block: bool) -> Self {
+        self.add(
+            PIOInstruction::PUSH {
+                delay_sideset: 0,
+                if_full,
+                block,
+            },
+            None,
+        );
+        self
+    }
+
+    pub fn pull(mut self, if_empty: bool, block: bool) -> Self {
+        self.add(
+            PIOInstruction::PULL {
+                delay_sideset: 0,
+                if_empty,
+                block,
+            },
+            None,
+        );
+        self
+    }
+
+    pub fn mov(mut self, destination: MovDestination, op: MovOp, source: MovSource) -> Self {
+        self.add(
+            PIOInstruction::MOV {
+                delay_sideset: 0,
+                destination,
+                op,
+                source,
+            },
+            None,
+        );
+        self
+    }
+
+    /// Assembles to `mov y, y`, like pioasm
+    pub fn nop(self) -> Self {
+        self.mov(MovDestination::Y, MovOp::None, MovSource::Y)
+    }
+
+    /// `index` includes the relative flag 0x10
+    pub fn irq(mut self, clear: bool, wait: bool, index: u8) -> Self {
+        self.add(
+            PIOInstruction::IRQ {
+                delay_sideset: 0,
+                clear,
+                wait,
+                index,
+            },
+            None,
+        );
+        self
+    }
+
+    pub fn set(mut self, destination: SetDestination, data: u8) -> Self {
+        self.add(
+            PIOInstruction::SET {
+                delay_sideset: 0,
+                destination,
+                data,
+            },
+            None,
+        );
+        self
+    }
+
+    /// Resolves labels, checks every field against the side-set configuration and encodes the
+    /// program. Wrap defaults to the whole program, as in pioasm.
+    pub fn build(self) -> Result<Program, std::string::String> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        let max_count = if self.side_set.optional { 4 } else { 5 };
+        if self.side_set.count > max_count {
+            return Err(format!(
+                "Side-set count {} is larger than the maximum of {}",
+                self.side_set.count, max_count
+            ));
+        }
+        if self.instructions.is_empty() {
+            return Err(format!("Program {} has no instructions", self.name));
+        }
+        if self.instructions.len() > IMEM_SIZE as usize {
+            return Err(format!(
+                "Program {} is {} instructions long, instruction memory holds {}",
+                self.name,
+                self.instructions.len(),
+                IMEM_SIZE
+            ));
+        }
+        if let Some(origin) = self.origin {
+            if origin as u32 >= IMEM_SIZE {
+                return Err(format!("Origin {} is out of range", origin));
+            }
+        }
+
+        let mut words = Vec::with_capacity(self.instructions.len());
+        for (index, pending) in self.instructions.iter().enumerate() {
+            let mut instruction = pending.instruction;
+            if let (PIOInstruction::JMP { address, .. }, Some(target)) =
+                (&mut instruction, &pending.target)
+            {
+                *address = self
+                    .labels
+                    .iter()
+                    .find(|(label, _)| label == target)
+                    .map(|(_, address)| *address as u8)
+                    .ok_or(format!("Undefined label : {}", target))?;
+            }
+            let word = instruction
+                .encode_with_side_set(&self.side_set, pending.side, pending.delay)
+                .map_err(|err| format!("Instruction {}: {}", index, err))?;
+            words.push(word);
+        }
+
+        let symbols = self
+            .labels
+            .iter()
+            .map(|(name, address)| Symbol {
+                name: name.clone(),
+                value: *address as i64,
+                is_label: true,
+                public: false,
+            })
+            .collect();
+
+        Ok(Program {
+            name: self.name,
+            wrap_target: self.wrap_target.unwrap_or(0) as u8,
+            wrap: self.wrap.unwrap_or(words.len() - 1) as u8,
+            instructions: words,
+            origin: self.origin,
+            side_set: self.side_set,
+            symbols,
+            lang_opts: Vec::new(),
+            code_blocks: Vec::new(),
+        })
+    }
+
+    fn add(&mut self, instruction: PIOInstruction, target: Option<String>) {
+        self.instructions.push(BuilderInstruction {
+            instruction,
+            target,
+            side: None,
+            delay: 0,
+        });
+    }
+
+    /// IN and OUT encode a count of 32 as 0
+    fn bit_count(&mut self, bit_count: u8) -> u8 {
+        match bit_count {
+            1..=31 => bit_count,
+            32 => 0,
+            _ => {
+                self.fail(format!("Bit count {} is out of range", bit_count));
+                0
+            }
+        }
+    }
+
+    fn fail(&mut self, err: String) {
+        if self.error.is_none() {
+            self.error = Some(err);
+        }
+    }
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum JmpCondition {
     Always = 0,
     ScratchXZero = 1,
@@ -185,7 +966,7 @@ impl TryFrom<u8> for JmpCondition {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WaitPolarity {
     Zero = 0,
     One = 1,
@@ -204,7 +985,7 @@ impl TryFrom<u8> for WaitPolarity {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WaitSource {
     GPIO = 0,
     Pin = 1,
@@ -227,7 +1008,7 @@ impl TryFrom<u8> for WaitSource {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InSource {
     PINS = 0,
     X = 1,
@@ -258,7 +1039,7 @@ impl TryFrom<u8> for InSource {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OutDestination {
     PINS = 0,
     X = 1,
@@ -289,7 +1070,7 @@ impl TryFrom<u8> for OutDestination {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MovDestination {
     PINS = 0,
     X = 1,
@@ -320,12 +1101,12 @@ impl TryFrom<u8> for MovDestination {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MovOp {
     None = 0,
     Invert = 1,
     BitReverse = 2,
-    Reserved = 4,
+    Reserved = 3,
 }
 
 impl TryFrom<u8> for MovOp {
@@ -343,7 +1124,7 @@ impl TryFrom<u8> for MovOp {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MovSource {
     PINS = 0,
     X = 1,
@@ -374,7 +1155,7 @@ impl TryFrom<u8> for MovSource {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SetDestination {
     PINS = 0,
     X = 1,
@@ -403,3 +1184,95 @@ impl TryFrom<u8> for SetDestination {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_word_round_trips_or_is_reserved() {
+        for word in 0..=0xffffu16 {
+            let instruction = PIOInstruction::decode(word as u32).unwrap();
+            if is_reserved_word(word) {
+                assert_eq!(
+                    disassemble_word(word, &SideSet::default()),
+                    "reserved",
+                    "0x{:04x}",
+                    word
+                );
+            } else {
+                assert_eq!(instruction.encode(), Ok(word), "0x{:04x}", word);
+                assert_eq!(
+                    PIOInstruction::decode(instruction.encode().unwrap() as u32).unwrap(),
+                    instruction
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn encode_packs_side_set_and_delay() {
+        let nop = PIOInstruction::MOV {
+            delay_sideset: 0,
+            destination: MovDestination::Y,
+            op: MovOp::None,
+            source: MovSource::Y,
+        };
+        let side_set = SideSet {
+            count: 1,
+            optional: false,
+            pindirs: false,
+        };
+        assert_eq!(nop.encode_with_side_set(&side_set, Some(0), 4), Ok(0xa442));
+        assert!(nop.encode_with_side_set(&side_set, None, 4).is_err());
+        assert!(nop.encode_with_side_set(&side_set, Some(2), 0).is_err());
+        assert!(nop.encode_with_side_set(&side_set, Some(1), 16).is_err());
+
+        let optional = SideSet {
+            count: 1,
+            optional: true,
+            pindirs: false,
+        };
+        assert_eq!(nop.encode_with_side_set(&optional, Some(1), 0), Ok(0xb842));
+        assert_eq!(nop.encode_with_side_set(&optional, None, 3), Ok(0xa342));
+    }
+
+    #[test]
+    fn builder_resolves_labels_and_wrap() {
+        let program = Program::builder()
+            .name("ws2812")
+            .side_set(1, false)
+            .wrap_target()
+            .label("bitloop")
+            .out(OutDestination::X, 1)
+            .side(0)
+            .delay(2)
+            .jmp(JmpCondition::ScratchXZero, "do_zero")
+            .side(1)
+            .delay(1)
+            .jmp(JmpCondition::Always, "bitloop")
+            .side(1)
+            .delay(4)
+            .label("do_zero")
+            .nop()
+            .side(0)
+            .delay(4)
+            .wrap()
+            .build()
+            .unwrap();
+        assert_eq!(program.instructions, vec![0x6221, 0x1123, 0x1400, 0xa442]);
+        assert_eq!((program.wrap_target, program.wrap), (0, 3));
+
+        assert!(Program::builder()
+            .jmp(JmpCondition::Always, "missing")
+            .build()
+            .is_err());
+        assert!(Program::builder()
+            .side_set(1, false)
+            .nop()
+            .delay(16)
+            .side(0)
+            .build()
+            .is_err());
+    }
+}
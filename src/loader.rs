@@ -0,0 +1,401 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+use crate::instructions::*;
+use crate::memory_backing::IMEM_SIZE;
+
+/// The part of a `.pio.h` header being read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Top,
+    /// `static const uint16_t x_program_instructions[] = {`
+    Instructions(usize),
+    /// `static const struct pio_program x_program = {`
+    Struct(usize),
+    /// `static inline pio_sm_config x_program_get_default_config(uint offset) {`
+    Config(usize),
+}
+
+/// A `#define` with a numeric value, kept with its line for error messages
+struct Define {
+    line_no: usize,
+    line: String,
+    name: String,
+    value: i64,
+    is_label: bool,
+}
+
+fn parse_c_int(text: &str) -> Option<i64> {
+    let text = text.trim().trim_end_matches(|c| c == 'u' || c == 'U');
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// `name` with `suffix` stripped, for declarations such as `x_program_instructions[]`
+fn declared_name<'a>(line: &'a str, prefix: &str, suffix: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(prefix)?.trim_start();
+    let end = rest.find(suffix)?;
+    Some(&rest[..end]).filter(|name| !name.is_empty())
+}
+
+fn check_length(program: &Program) -> Result<(), std::string::String> {
+    if program.is_empty() || program.len() > IMEM_SIZE as usize {
+        return Err(format!(
+            "Program {} is {} instructions long, instruction memory holds {}",
+            program.name,
+            program.len(),
+            IMEM_SIZE
+        ));
+    }
+    Ok(())
+}
+
+/// Reads every program out of a header generated by pioasm's `c-sdk` output: the instruction
+/// array, `.origin`, the wrap and symbol defines, and the side-set set up by
+/// `x_program_get_default_config`. `PIO::apply_program_config` applies that configuration.
+pub fn parse_header(text: &str) -> Result<Vec<Program>, std::string::String> {
+    let mut programs: Vec<Program> = Vec::new();
+    let mut lengths: Vec<Option<usize>> = Vec::new();
+    let mut defines: Vec<Define> = Vec::new();
+    let mut section = Section::Top;
+
+    for (line_idx, raw_line) in text.lines().enumerate() {
+        let line_no = line_idx + 1;
+        let line = raw_line.split("//").next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = |what: &str| format!("line {}: invalid {} : {}", line_no, what, line);
+
+        match section {
+            Section::Top => {
+                if let Some(define) = line.strip_prefix("#define") {
+                    let mut words = define.split_whitespace();
+                    if let (Some(name), Some(value), None) =
+                        (words.next(), words.next(), words.next())
+                    {
+                        if let Some(number) = parse_c_int(value) {
+                            // pioasm marks label offsets as unsigned
+                            let is_label = value.ends_with('u');
+                            defines.push(Define {
+                                line_no,
+                                line: line.to_string(),
+                                name: name.to_string(),
+                                value: number,
+                                is_label,
+                            });
+                        }
+                    }
+                } else if let Some(name) =
+                    declared_name(line, "static const uint16_t", "_program_instructions[]")
+                {
+                    programs.push(Program {
+                        name: name.to_string(),
+                        ..Program::default()
+                    });
+                    lengths.push(None);
+                    section = Section::Instructions(programs.len() - 1);
+                } else if let Some(name) =
+                    declared_name(line, "static const struct pio_program", "_program ")
+                {
+                    let index = programs
+                        .iter()
+                        .position(|program| program.name == name)
+                        .ok_or_else(|| format!("line {}: unknown program {}", line_no, name))?;
+                    section = Section::Struct(index);
+                } else if let Some(name) = declared_name(
+                    line,
+                    "static inline pio_sm_config",
+                    "_program_get_default_config(",
+                ) {
+                    if let Some(index) = programs.iter().position(|program| program.name == name) {
+                        section = Section::Config(index);
+                    }
+                }
+            }
+            Section::Instructions(index) => {
+                let (words, end) = match line.strip_suffix("};") {
+                    Some(words) => (words, true),
+                    None => (line, false),
+                };
+                for word in words
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|word| !word.is_empty())
+                {
+                    let word = parse_c_int(word)
+                        .filter(|word| (0..=0xffff).contains(word))
+                        .ok_or_else(|| invalid("instruction word"))?;
+                    programs[index].instructions.push(word as u16);
+                }
+                if end {
+                    section = Section::Top;
+                }
+            }
+            Section::Struct(index) => {
+                if line.starts_with("};") {
+                    section = Section::Top;
+                } else if let Some(value) = line.strip_prefix(".origin") {
+                    let origin = value
+                        .trim_start_matches(|c: char| c == '=' || c.is_whitespace())
+                        .trim_end_matches(',');
+                    programs[index].origin = match parse_c_int(origin) {
+                        Some(-1) => None,
+                        Some(origin) if (0..IMEM_SIZE as i64).contains(&origin) => {
+                            Some(origin as u8)
+                        }
+                        _ => return Err(invalid(".origin")),
+                    };
+                } else if let Some(value) = line.strip_prefix(".length") {
+                    let length = value
+                        .trim_start_matches(|c: char| c == '=' || c.is_whitespace())
+                        .trim_end_matches(',');
+                    lengths[index] =
+                        Some(parse_c_int(length).ok_or_else(|| invalid(".length"))? as usize);
+                }
+            }
+            Section::Config(index) => {
+                if line == "}" {
+                    section = Section::Top;
+                } else if let Some(arguments) = line
+                    .strip_prefix("sm_config_set_sideset(")
+                    .and_then(|rest| rest.strip_suffix(");"))
+                {
+                    let arguments: Vec<&str> = arguments.split(',').map(str::trim).collect();
+                    let side_set = match arguments.as_slice() {
+                        ["&c", bits, optional, pindirs] => {
+                            let bits = parse_c_int(bits)
+                                .filter(|bits| (0..=5).contains(bits))
+                                .ok_or_else(|| invalid("side-set"))?
+                                as u8;
+                            let optional = *optional == "true";
+                            if optional && bits == 0 {
+                                return Err(invalid("side-set"));
+                            }
+                            SideSet {
+                                count: bits - optional as u8,
+                                optional,
+                                pindirs: *pindirs == "true",
+                            }
+                        }
+                        _ => return Err(invalid("side-set")),
+                    };
+                    programs[index].side_set = side_set;
+                }
+            }
+        }
+    }
+
+    if section != Section::Top {
+        return Err("Header ends inside a declaration".to_string());
+    }
+    if programs.is_empty() {
+        return Err("No PIO programs found in header".to_string());
+    }
+
+    for (program, length) in programs.iter_mut().zip(lengths) {
+        check_length(program)?;
+        if let Some(length) = length {
+            if length != program.len() {
+                return Err(format!(
+                    "Program {} declares a length of {} but has {} instructions",
+                    program.name,
+                    length,
+                    program.len()
+                ));
+            }
+        }
+        program.wrap = program.len() as u8 - 1;
+    }
+
+    for Define {
+        line_no,
+        line,
+        name,
+        value,
+        is_label,
+    } in defines.iter()
+    {
+        let wrap_value = |what: &str| {
+            u8::try_from(*value)
+                .map_err(|_| format!("line {}: invalid {} : {}", line_no, what, line))
+        };
+        // "uart_rx_wrap" belongs to uart_rx rather than to a program named uart
+        let program = match programs
+            .iter_mut()
+            .filter(|program| name.starts_with(&format!("{}_", program.name)))
+            .max_by_key(|program| program.name.len())
+        {
+            Some(program) => program,
+            None => continue,
+        };
+        match &name[program.name.len() + 1..] {
+            "wrap_target" => program.wrap_target = wrap_value("wrap target")?,
+            "wrap" => program.wrap = wrap_value("wrap")?,
+            name => {
+                let (name, is_label) = match name.strip_prefix("offset_") {
+                    Some(label) if *is_label => (label, true),
+                    _ => (name, false),
+                };
+                program.symbols.push(Symbol {
+                    name: name.to_string(),
+                    value: *value,
+                    is_label,
+                    public: true,
+                });
+            }
+        }
+    }
+
+    for program in programs.iter() {
+        if program.wrap_target > program.wrap || program.wrap as usize >= program.len() {
+            return Err(format!(
+                "Program {} wraps from {} to {} outside of its {} instructions",
+                program.name,
+                program.wrap,
+                program.wrap_target,
+                program.len()
+            ));
+        }
+    }
+    Ok(programs)
+}
+
+/// Reads pioasm's `hex` output, one instruction word per line. The file holds no configuration,
+/// so the program wraps over its whole length without side-set.
+pub fn parse_hex(name: &str, text: &str) -> Result<Program, std::string::String> {
+    let mut program = Program {
+        name: name.to_string(),
+        ..Program::default()
+    };
+    for (line_idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let word = u16::from_str_radix(line, 16)
+            .map_err(|_| format!("line {}: invalid instruction word : {}", line_idx + 1, line))?;
+        program.instructions.push(word);
+    }
+    check_length(&program)?;
+    program.wrap = program.len() as u8 - 1;
+    Ok(program)
+}
+
+pub fn load_header(path: &Path) -> Result<Vec<Program>, std::string::String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{} : {}", path.display(), err))?;
+    parse_header(&text).map_err(|err| format!("{} : {}", path.display(), err))
+}
+
+/// The program is named after the file
+pub fn load_hex(path: &Path) -> Result<Program, std::string::String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{} : {}", path.display(), err))?;
+    let name = path.file_stem().map_or("program".to_string(), |stem| {
+        stem.to_string_lossy().to_string()
+    });
+    parse_hex(&name, &text).map_err(|err| format!("{} : {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::output::c_sdk;
+
+    const SQUAREWAVE_H: &str = include_str!("../testdata/squarewave.pio.h");
+
+    #[test]
+    fn c_sdk_output_round_trips() {
+        let assembly = assemble(&format!(
+            "{}\n{}",
+            include_str!("../testdata/ws2812.pio"),
+            ".program placed
+.side_set 2 opt pindirs
+.origin 5
+public start:
+    set x, 1 side 1
+.wrap_target
+    jmp start [3]
+.wrap"
+        ))
+        .unwrap();
+
+        let programs = parse_header(&c_sdk(&assembly)).unwrap();
+        assert_eq!(programs.len(), assembly.programs.len());
+        for (loaded, assembled) in programs.iter().zip(assembly.programs.iter()) {
+            assert_eq!(loaded.name, assembled.name);
+            assert_eq!(loaded.instructions, assembled.instructions);
+            assert_eq!(loaded.origin, assembled.origin);
+            assert_eq!(
+                (loaded.wrap_target, loaded.wrap),
+                (assembled.wrap_target, assembled.wrap)
+            );
+            assert_eq!(loaded.side_set, assembled.side_set, "{}", loaded.name);
+            assert_eq!(
+                loaded.symbols,
+                assembled.public_symbols().cloned().collect::<Vec<_>>()
+            );
+        }
+        assert_eq!(programs[2].origin, Some(5));
+        assert_eq!(programs[2].symbols[0].name, "start");
+        assert!(programs[2].symbols[0].is_label);
+    }
+
+    #[test]
+    fn header_errors_name_the_problem() {
+        let cases = [
+            (
+                SQUAREWAVE_H.replace(".length = 4", ".length = 5"),
+                "Program squarewave declares a length of 5 but has 4 instructions",
+            ),
+            (
+                SQUAREWAVE_H.replace(".origin = -1", ".origin = 32"),
+                "invalid .origin : .origin = 32,",
+            ),
+            (
+                SQUAREWAVE_H[..SQUAREWAVE_H.find("0x0001").unwrap()].to_string(),
+                "Header ends inside a declaration",
+            ),
+            (
+                SQUAREWAVE_H.replace("0xe000", "0xe0g0"),
+                "line 22: invalid instruction word : 0xe0g0,",
+            ),
+            (
+                SQUAREWAVE_H.replace("0xe000", "0x10000"),
+                "invalid instruction word",
+            ),
+            (
+                SQUAREWAVE_H.replace("#define squarewave_wrap 3", "#define squarewave_wrap 259"),
+                "line 16: invalid wrap : #define squarewave_wrap 259",
+            ),
+            (
+                SQUAREWAVE_H.replace("_wrap_target 0", "_wrap_target -1"),
+                "line 15: invalid wrap target : #define squarewave_wrap_target -1",
+            ),
+            (
+                "#define nothing 1\n".to_string(),
+                "No PIO programs found in header",
+            ),
+        ];
+        for (header, message) in cases.iter() {
+            let err = parse_header(header).unwrap_err();
+            assert!(err.contains(message), "{} : {}", message, err);
+        }
+    }
+
+    #[test]
+    fn hex_output_loads_and_rejects_bad_words() {
+        let program = parse_hex("squarewave", include_str!("../testdata/squarewave.hex")).unwrap();
+        assert_eq!(program.instructions, vec![0xe081, 0xe101, 0xe000, 0x0001]);
+        assert_eq!((program.wrap_target, program.wrap), (0, 3));
+
+        assert_eq!(
+            parse_hex("bad", "e081\n\nzz\n").unwrap_err(),
+            "line 3: invalid instruction word : zz"
+        );
+        assert!(parse_hex("empty", "\n").is_err());
+    }
+}
@@ -1,13 +1,41 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::collections::BTreeMap;
+use std::path::Path;
 
+mod assembler;
 mod instructions;
+mod loader;
 use instructions::*;
 mod memory_backing;
 use memory_backing::*;
 mod gpio;
+mod netlist;
+mod output;
+mod pin_names;
+mod register_dump;
+mod sio;
+mod state_file;
 mod state_machine;
-use tock_registers::interfaces::Readable;
+mod worker;
+use memoffset::offset_of;
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+
+/// Identifies a hook added with `PIO::watch_instruction_writes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct InstructionWatchId(u32);
+
+/// Called with the address, the previous word and the new word of every instruction memory write
+struct InstructionWatch {
+    id: InstructionWatchId,
+    callback: Box<dyn FnMut(u8, u16, u16) + Send>,
+}
+
+impl std::fmt::Debug for InstructionWatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InstructionWatch")
+            .field("id", &self.id)
+            .finish()
+    }
+}
 
 #[derive(Debug, Default)]
 pub struct PIO {
@@ -16,14 +44,23 @@ pub struct PIO {
     sm2: state_machine::PIOStateMachine,
     sm3: state_machine::PIOStateMachine,
     mmio: memory_backing::PIOMemoryBacking,
-    gpio: Rc<RefCell<gpio::GPIO>>,
+    gpio: gpio::SharedGPIO,
+    block_idx: u8,
+    gpio_base: u32,
     irq_flags: [u8; 8],
     delay_count: u32,
     sm_id: u32,
+    strict_access: bool,
+    /// Instruction memory slots taken by `load_program`, one bit per slot
+    used_instruction_space: u32,
+    /// Length of every loaded program by offset, so `remove_program` only frees what was loaded
+    loaded_programs: BTreeMap<u8, usize>,
+    instruction_watches: Vec<InstructionWatch>,
+    next_instruction_watch: u32,
 }
 
 impl PIO {
-    pub fn new(state_machine_idx: u32, gpio: Rc<RefCell<gpio::GPIO>>) -> Self {
+    pub fn new(block_idx: u8, state_machine_idx: u32, gpio: gpio::SharedGPIO) -> Self {
         let pio = PIO {
             sm0: state_machine::PIOStateMachine::default(),
             sm1: state_machine::PIOStateMachine::default(),
@@ -32,12 +69,58 @@ impl PIO {
             mmio: memory_backing::PIOMemoryBacking::default(),
             irq_flags: [0; 8],
             gpio: gpio.clone(),
+            block_idx,
+            gpio_base: 0,
             delay_count: 0,
             sm_id: state_machine_idx,
+            strict_access: false,
+            used_instruction_space: 0,
+            loaded_programs: BTreeMap::new(),
+            instruction_watches: Vec::new(),
+            next_instruction_watch: 0,
         };
         pio
     }
 
+    /// Puts every register and state machine back into its reset state. Instruction memory is
+    /// cleared as well, so programs need to be loaded again afterwards.
+    pub fn reset(&mut self) {
+        let previous: Vec<u16> = self.mmio.instructions().collect();
+        self.mmio.reset();
+        self.notify_instruction_writes(0, &previous);
+        self.sm0.reset();
+        self.sm1.reset();
+        self.sm2.reset();
+        self.sm3.reset();
+        self.irq_flags = [0; 8];
+        self.delay_count = 0;
+        self.used_instruction_space = 0;
+        self.loaded_programs.clear();
+
+        let borrowed_gpio: &mut gpio::GPIO = &mut gpio::lock(&self.gpio);
+        for sm in 0..SM_COUNT as u8 {
+            borrowed_gpio.release_driver(gpio::GPIODriver::StateMachine {
+                pio: self.block_idx,
+                sm,
+            });
+        }
+    }
+
+    pub fn gpio(&self) -> gpio::SharedGPIO {
+        self.gpio.clone()
+    }
+
+    /// Moves the 32 pin window this block sees to start at physical pin `gpio_base`, like the
+    /// GPIOBASE register on packages with more than 32 pins
+    pub fn set_gpio_base(&mut self, gpio_base: u32) -> Result<(), std::string::String> {
+        let pin_count = gpio::lock(&self.gpio).get_pin_count() as u32;
+        if gpio_base > 32 || gpio_base > pin_count {
+            return Err(format!("Invalid GPIO base : {}", gpio_base));
+        }
+        self.gpio_base = gpio_base;
+        Ok(())
+    }
+
     pub fn run(&mut self) -> Result<(), std::string::String> {
         loop {
             self.step()?
@@ -47,15 +130,42 @@ impl PIO {
     }
 
     pub fn step(&mut self) -> Result<(), std::string::String> {
+        let step_result = self.execute_step();
+        gpio::lock(&self.gpio).tick();
+        self.update_debug_registers()?;
+        step_result
+    }
+
+    fn execute_step(&mut self) -> Result<(), std::string::String> {
         if self.delay_count != 0 {
             self.delay_count -= 1;
             return Ok(());
         }
 
         let pc = self.get_current_sm()?.get_pc();
-        let instr = instructions::PIOInstruction::decode(self.mmio.get_pc_data(pc)?)?;
-        println!("{} : {:?}", self.get_current_sm()?.get_pc(), instr);
+        let instr_data = self.mmio.get_pc_data(pc)?;
+        let instr = instructions::PIOInstruction::decode(instr_data)?;
+        let side_set = self.side_set_config(self.sm_id)?;
+        let pins = self.instruction_pins(&instr, instr_data, &side_set)?;
+        if pins == 0 {
+            println!("{:2}: {}", pc, instr.disassemble(&side_set).trim_end());
+            return self.execute_instruction(instr);
+        }
+
+        // The pins an instruction touches are named in the trace and in any error it raises
+        let labels = gpio::lock(&self.gpio).pin_labels(pins);
+        println!("{:2}: {}; {}", pc, instr.disassemble(&side_set), labels);
+        self.execute_instruction(instr).map_err(|err| {
+            format!(
+                "{} on {} : {}",
+                instr.disassemble(&side_set).trim_end(),
+                labels,
+                err
+            )
+        })
+    }
 
+    fn execute_instruction(&mut self, instr: PIOInstruction) -> Result<(), std::string::String> {
         match instr {
             PIOInstruction::JMP {
                 delay_sideset,
@@ -116,8 +226,8 @@ impl PIO {
                             _ => return Err(format!("Invalid State Machine ID : {}", self.sm_id)),
                         };
 
-                        let borrowed_gpio: &gpio::GPIO = &self.gpio.borrow();
-                        if borrowed_gpio.get_idx(pin as usize) == 1 {
+                        let borrowed_gpio: &gpio::GPIO = &gpio::lock(&self.gpio);
+                        if borrowed_gpio.get_idx(self.pio_pin(pin)) == 1 {
                             true
                         } else {
                             false
@@ -154,61 +264,50 @@ impl PIO {
                 source,
                 index,
             } => {
-                self.process_delay_sideset(delay_sideset)?;
-
                 let polarity_value = match polarity {
                     WaitPolarity::One => 1,
                     WaitPolarity::Zero => 0,
                 };
 
-                loop {
-                    match source {
-                        WaitSource::GPIO => {
-                            let borrowed_gpio: &gpio::GPIO = &self.gpio.borrow();
-                            if borrowed_gpio.get_idx(index as usize) == polarity_value {
-                                break;
-                            }
-                        }
-                        WaitSource::Pin => {
-                            let in_base = match self.sm_id {
-                                0 => self.mmio.SM0_PINCTRL.read(SM_PINCTRL::IN_BASE),
-                                1 => self.mmio.SM1_PINCTRL.read(SM_PINCTRL::IN_BASE),
-                                2 => self.mmio.SM2_PINCTRL.read(SM_PINCTRL::IN_BASE),
-                                3 => self.mmio.SM3_PINCTRL.read(SM_PINCTRL::IN_BASE),
-                                _ => {
-                                    return Err(format!(
-                                        "Invalid State Machine ID : {}",
-                                        self.sm_id
-                                    ))
-                                }
-                            };
-
-                            let borrowed_gpio: &gpio::GPIO = &self.gpio.borrow();
-                            if borrowed_gpio.get_idx(((index as u32 + in_base) % 32) as usize)
-                                == polarity_value
-                            {
-                                break;
-                            }
-                        }
-                        WaitSource::IRQ => {
-                            if index > self.irq_flags.len() as u8 {
-                                return Err(format!(
-                                    "IRQ Index longer than total flag length : {}",
-                                    index
-                                ));
-                            } else {
-                                if self.irq_flags[index as usize] == polarity_value {
-                                    break;
-                                }
-                            }
-                        }
-                        WaitSource::Reserved => {
-                            unimplemented!("WaitSource::Reserved!");
+                let level = match source {
+                    WaitSource::GPIO => {
+                        let borrowed_gpio: &gpio::GPIO = &gpio::lock(&self.gpio);
+                        borrowed_gpio.get_idx(self.pio_pin(index as u32))
+                    }
+                    WaitSource::Pin => {
+                        let in_base = match self.sm_id {
+                            0 => self.mmio.SM0_PINCTRL.read(SM_PINCTRL::IN_BASE),
+                            1 => self.mmio.SM1_PINCTRL.read(SM_PINCTRL::IN_BASE),
+                            2 => self.mmio.SM2_PINCTRL.read(SM_PINCTRL::IN_BASE),
+                            3 => self.mmio.SM3_PINCTRL.read(SM_PINCTRL::IN_BASE),
+                            _ => return Err(format!("Invalid State Machine ID : {}", self.sm_id)),
+                        };
+
+                        let borrowed_gpio: &gpio::GPIO = &gpio::lock(&self.gpio);
+                        borrowed_gpio.get_idx(self.pio_pin(index as u32 + in_base))
+                    }
+                    WaitSource::IRQ => {
+                        if index as usize >= self.irq_flags.len() {
+                            return Err(format!(
+                                "IRQ Index longer than total flag length : {}",
+                                index
+                            ));
                         }
+                        self.irq_flags[index as usize]
+                    }
+                    WaitSource::Reserved => {
+                        unimplemented!("WaitSource::Reserved!");
                     }
+                };
+
+                // A stalled WAIT is executed again on the next step, so the GPIO keeps ticking
+                // and stimulus can release it. The delay only starts once the wait is over.
+                if level != polarity_value {
+                    return Ok(());
                 }
 
-                self.get_current_sm()?.inc_pc();
+                self.process_delay_sideset(delay_sideset)?;
+                self.get_current_sm()?.inc_pc()?;
             }
             PIOInstruction::IN {
                 delay_sideset,
@@ -350,8 +449,14 @@ impl PIO {
                             _ => return Err(format!("Invalid State Machine ID : {}", self.sm_id)),
                         };
 
-                        let borrowed_gpio: &mut gpio::GPIO = &mut self.gpio.borrow_mut();
-                        borrowed_gpio.set_idx_continuous(set_base, set_count, data)?;
+                        let borrowed_gpio: &mut gpio::GPIO = &mut gpio::lock(&self.gpio);
+                        borrowed_gpio.set_idx_continuous(
+                            self.current_driver(),
+                            self.gpio_base,
+                            set_base,
+                            set_count,
+                            data,
+                        )?;
                     }
                     SetDestination::X => {
                         self.get_current_sm()?.set_scratch_x(data.into())?;
@@ -376,8 +481,14 @@ impl PIO {
                             _ => return Err(format!("Invalid State Machine ID : {}", self.sm_id)),
                         };
 
-                        let borrowed_gpio: &mut gpio::GPIO = &mut self.gpio.borrow_mut();
-                        borrowed_gpio.set_idx_enable_continuous(set_base, set_count, data)?;
+                        let borrowed_gpio: &mut gpio::GPIO = &mut gpio::lock(&self.gpio);
+                        borrowed_gpio.set_idx_enable_continuous(
+                            self.current_driver(),
+                            self.gpio_base,
+                            set_base,
+                            set_count,
+                            data,
+                        )?;
                     }
                     SetDestination::Reserved0
                     | SetDestination::Reserved1
@@ -400,12 +511,456 @@ impl PIO {
         Ok(())
     }
 
+    /// When enabled, register writes that the hardware would silently drop (read-only fields,
+    /// reserved bits, full TX FIFOs) are reported as errors by `write_register`. So are writes to
+    /// `SMx_INSTR`, which execute the instruction on hardware. That is not emulated, so without
+    /// strict access the write is dropped and `SMx_INSTR` keeps showing `INSTR_MEM[pc]`.
+    pub fn set_strict_access(&mut self, strict: bool) {
+        self.strict_access = strict;
+    }
+
+    pub fn read_register(&mut self, offset: usize) -> Result<u32, std::string::String> {
+        self.update_debug_registers()?;
+
+        let rxf0 = offset_of!(PIOMemoryBacking, RXF0);
+        if (rxf0..rxf0 + 0x10).contains(&offset) {
+            let sm_id = ((offset - rxf0) / 4) as u32;
+            if self.get_sm(sm_id)?.rx_fifo_empty() {
+                let rxunder = self.mmio.FDEBUG.read(FDEBUG::RXUNDER) | (1 << sm_id);
+                self.mmio.FDEBUG.modify(FDEBUG::RXUNDER.val(rxunder));
+                return Ok(0);
+            }
+            let value = self.get_sm(sm_id)?.pop_from_rx_fifo()?;
+            self.update_debug_registers()?;
+            return Ok(value);
+        }
+
+        self.mmio.bus_read(offset)
+    }
+
+    /// Captures every register after bringing the live ones up to date, for dumping with
+    /// `Display` or comparing with `RegisterSnapshot::diff`
+    pub fn snapshot(&mut self) -> Result<register_dump::RegisterSnapshot, std::string::String> {
+        self.update_debug_registers()?;
+        register_dump::RegisterSnapshot::capture(&self.mmio)
+    }
+
+    pub fn export_register_image(&mut self) -> Result<Vec<u8>, std::string::String> {
+        self.update_debug_registers()?;
+        Ok(self.mmio.to_image())
+    }
+
+    /// Loads a raw register image such as one dumped from hardware. Program counters and IRQ
+    /// flags are taken from `SMx_ADDR` and `IRQ`; everything else internal is left untouched
+    /// until a side-file is imported with `import_internal_state`.
+    ///
+    /// FIFO contents only live in the side-file. `FSTAT`, `FLEVEL`, `SMx_INSTR` and the
+    /// `DBG_PAD` registers are recomputed from the emulator state on the next step or register
+    /// read, so without a side-file the levels in the image are replaced by those of the current
+    /// FIFOs.
+    ///
+    /// The image does not say which programs its instruction memory holds. Every slot holding
+    /// something other than the reset value is taken, so `add_program` leaves it alone, and no
+    /// program can be removed until it is loaded again.
+    pub fn import_register_image(&mut self, image: &[u8]) -> Result<(), std::string::String> {
+        let previous: Vec<u16> = self.mmio.instructions().collect();
+        self.mmio.load_image(image)?;
+        self.notify_instruction_writes(0, &previous);
+        self.loaded_programs.clear();
+        self.used_instruction_space = self
+            .mmio
+            .instructions()
+            .enumerate()
+            .filter(|(_, word)| *word != 0)
+            .fold(0, |used, (address, _)| used | 1 << address);
+
+        for sm_id in 0..SM_COUNT {
+            let pc = self.mmio.sm_addr(sm_id)?.read(SM_ADDR::CURRENT_ADDRESS);
+            self.get_sm(sm_id)?.set_pc(pc)?;
+        }
+        let irq = self.mmio.IRQ.read(IRQ::IRQFLAGS);
+        for (idx, flag) in self.irq_flags.iter_mut().enumerate() {
+            *flag = ((irq >> idx) & 1) as u8;
+        }
+
+        Ok(())
+    }
+
+    pub fn export_internal_state(&self) -> Result<std::string::String, std::string::String> {
+        state_file::InternalState {
+            state_machines: vec![
+                self.sm0.clone(),
+                self.sm1.clone(),
+                self.sm2.clone(),
+                self.sm3.clone(),
+            ],
+            irq_flags: self.irq_flags,
+            delay_count: self.delay_count,
+        }
+        .to_json()
+    }
+
+    pub fn import_internal_state(&mut self, json: &str) -> Result<(), std::string::String> {
+        let mut state = state_file::InternalState::from_json(json)?;
+
+        self.sm3 = state.state_machines.pop().unwrap_or_default();
+        self.sm2 = state.state_machines.pop().unwrap_or_default();
+        self.sm1 = state.state_machines.pop().unwrap_or_default();
+        self.sm0 = state.state_machines.pop().unwrap_or_default();
+        self.irq_flags = state.irq_flags;
+        self.delay_count = state.delay_count;
+
+        self.update_debug_registers()
+    }
+
+    /// Writes the register image to `image_path` and, if given, the internal state side-file to
+    /// `state_path`
+    pub fn save_state(
+        &mut self,
+        image_path: &Path,
+        state_path: Option<&Path>,
+    ) -> Result<(), std::string::String> {
+        let image = self.export_register_image()?;
+        std::fs::write(image_path, image)
+            .map_err(|err| format!("Failed to write {} : {}", image_path.display(), err))?;
+
+        if let Some(state_path) = state_path {
+            let json = self.export_internal_state()?;
+            std::fs::write(state_path, json)
+                .map_err(|err| format!("Failed to write {} : {}", state_path.display(), err))?;
+        }
+        Ok(())
+    }
+
+    /// Reads back what `save_state` wrote. See `import_register_image` for what is lost when
+    /// `state_path` is `None`.
+    pub fn load_state(
+        &mut self,
+        image_path: &Path,
+        state_path: Option<&Path>,
+    ) -> Result<(), std::string::String> {
+        let image = std::fs::read(image_path)
+            .map_err(|err| format!("Failed to read {} : {}", image_path.display(), err))?;
+        self.import_register_image(&image)?;
+
+        if let Some(state_path) = state_path {
+            let json = std::fs::read_to_string(state_path)
+                .map_err(|err| format!("Failed to read {} : {}", state_path.display(), err))?;
+            self.import_internal_state(&json)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_register(&mut self, offset: usize, value: u32) -> Result<(), std::string::String> {
+        let instr_mem0 = offset_of!(PIOMemoryBacking, INSTR_MEM0);
+        let instruction = offset
+            .checked_sub(instr_mem0)
+            .map(|delta| delta / 4)
+            .filter(|address| *address < IMEM_SIZE as usize);
+        let previous =
+            instruction.map(|address| self.mmio.instruction_memory()[address].get() as u16);
+
+        let txf0 = offset_of!(PIOMemoryBacking, TXF0);
+        if self.strict_access {
+            let sm0_instr = offset_of!(PIOMemoryBacking, SM0_INSTR);
+            if let Some(sm_id) =
+                (0..SM_COUNT).find(|sm_id| offset == sm0_instr + *sm_id as usize * 0x18)
+            {
+                return Err(format!(
+                    "Write to SM{}_INSTR, executing instructions from the bus is not emulated",
+                    sm_id
+                ));
+            }
+            if (txf0..txf0 + 0x10).contains(&offset) {
+                let sm_id = ((offset - txf0) / 4) as u32;
+                if self.get_sm(sm_id)?.tx_fifo_full() {
+                    return Err(format!("Write to full TX FIFO of state machine {}", sm_id));
+                }
+            }
+        }
+
+        let strobes = self.mmio.bus_write(offset, value, self.strict_access)?;
+        if let (Some(address), Some(previous)) = (instruction, previous) {
+            self.notify_instruction_writes(address as u8, &[previous]);
+        }
+
+        if offset == offset_of!(PIOMemoryBacking, CTRL) {
+            for sm_id in 0..SM_COUNT {
+                if CTRL::SM_RESTART.read(strobes) & (1 << sm_id) != 0 {
+                    self.get_sm(sm_id)?.restart();
+                    if sm_id == self.sm_id {
+                        self.delay_count = 0;
+                    }
+                }
+                if CTRL::CLKDIV_RESTART.read(strobes) & (1 << sm_id) != 0 {
+                    self.get_sm(sm_id)?.restart_clock_divider();
+                }
+            }
+        } else if (txf0..txf0 + 0x10).contains(&offset) {
+            let sm_id = ((offset - txf0) / 4) as u32;
+            if self.get_sm(sm_id)?.tx_fifo_full() {
+                let txover = self.mmio.FDEBUG.read(FDEBUG::TXOVER) | (1 << sm_id);
+                self.mmio.FDEBUG.modify(FDEBUG::TXOVER.val(txover));
+            } else {
+                self.get_sm(sm_id)?.push_to_tx_fifo(value)?;
+            }
+        } else if offset == offset_of!(PIOMemoryBacking, IRQ) {
+            for (idx, flag) in self.irq_flags.iter_mut().enumerate() {
+                if value & (1 << idx) != 0 {
+                    *flag = 0;
+                }
+            }
+        } else if offset == offset_of!(PIOMemoryBacking, IRQ_FORCE) {
+            for (idx, flag) in self.irq_flags.iter_mut().enumerate() {
+                if value & (1 << idx) != 0 {
+                    *flag = 1;
+                }
+            }
+        }
+
+        self.update_debug_registers()
+    }
+
     pub fn set_instruction_data(
         &mut self,
         index: u8,
         data: u16,
     ) -> Result<(), std::string::String> {
-        self.mmio.set_instruction_data(index, data)
+        self.write_instructions(index, &[data])
+    }
+
+    /// Every instruction word, by address
+    pub fn instructions(&self) -> Vec<u16> {
+        self.mmio.instructions().collect()
+    }
+
+    pub fn read_instructions(
+        &self,
+        start: u8,
+        count: usize,
+    ) -> Result<Vec<u16>, std::string::String> {
+        self.mmio.read_instructions(start, count)
+    }
+
+    /// Writes `words` to consecutive addresses from `start`, telling the instruction watches
+    pub fn write_instructions(
+        &mut self,
+        start: u8,
+        words: &[u16],
+    ) -> Result<(), std::string::String> {
+        let previous = self.mmio.read_instructions(start, words.len())?;
+        self.mmio.write_instructions(start, words)?;
+        self.notify_instruction_writes(start, &previous);
+        Ok(())
+    }
+
+    /// Calls `callback` with the address, previous word and new word of every write to
+    /// instruction memory, whether through the bus, a program load, a reset or a register image
+    pub fn watch_instruction_writes<F>(&mut self, callback: F) -> InstructionWatchId
+    where
+        F: FnMut(u8, u16, u16) + Send + 'static,
+    {
+        let id = InstructionWatchId(self.next_instruction_watch);
+        self.next_instruction_watch += 1;
+        self.instruction_watches.push(InstructionWatch {
+            id,
+            callback: Box::new(callback),
+        });
+        id
+    }
+
+    pub fn unwatch_instruction_writes(&mut self, id: InstructionWatchId) {
+        self.instruction_watches.retain(|watch| watch.id != id);
+    }
+
+    /// `previous` holds the words from `start` as they were before the write
+    fn notify_instruction_writes(&mut self, start: u8, previous: &[u16]) {
+        if self.instruction_watches.is_empty() {
+            return;
+        }
+        let current = self.mmio.instruction_memory();
+        for (offset, old) in previous.iter().enumerate() {
+            let address = start as usize + offset;
+            let new = current[address].get() as u16;
+            for watch in self.instruction_watches.iter_mut() {
+                (watch.callback)(address as u8, *old, new);
+            }
+        }
+    }
+
+    /// Slots `program` would take when loaded at `offset`
+    fn program_mask(program: &Program, offset: u8) -> Result<u32, std::string::String> {
+        if program.is_empty() || offset as usize + program.len() > IMEM_SIZE as usize {
+            return Err(format!(
+                "Program {} of {} instructions does not fit at offset {}",
+                program.name,
+                program.len(),
+                offset
+            ));
+        }
+        Ok((((1u64 << program.len()) - 1) << offset) as u32)
+    }
+
+    /// Whether `program` can be loaded at `offset` without overlapping a loaded program
+    pub fn can_load_program_at(&self, program: &Program, offset: u8) -> bool {
+        if program.origin.is_some_and(|origin| origin != offset) {
+            return false;
+        }
+        match PIO::program_mask(program, offset) {
+            Ok(mask) => self.used_instruction_space & mask == 0,
+            Err(_) => false,
+        }
+    }
+
+    /// Copies `program` into instruction memory at `offset`, adding the offset to every JMP target
+    /// like `pio_add_program_at_offset` does. The wrap bounds follow once `apply_program_config`
+    /// is given the same offset.
+    pub fn load_program(
+        &mut self,
+        program: &Program,
+        offset: u8,
+    ) -> Result<u8, std::string::String> {
+        if let Some(origin) = program.origin {
+            if origin != offset {
+                return Err(format!(
+                    "Program {} must be loaded at its origin {}, not {}",
+                    program.name, origin, offset
+                ));
+            }
+        }
+        let mask = PIO::program_mask(program, offset)?;
+        if self.used_instruction_space & mask != 0 {
+            return Err(format!(
+                "Program {} at offset {} overlaps a loaded program",
+                program.name, offset
+            ));
+        }
+
+        let words: Vec<u16> = program
+            .instructions
+            .iter()
+            .map(|word| {
+                if word >> 13 == 0 {
+                    (word & !0x1f) | ((word + offset as u16) & 0x1f)
+                } else {
+                    *word
+                }
+            })
+            .collect();
+        self.write_instructions(offset, &words)?;
+        self.used_instruction_space |= mask;
+        self.loaded_programs.insert(offset, program.len());
+        Ok(offset)
+    }
+
+    /// Loads `program` at its `.origin`, or otherwise at the highest offset with room for it,
+    /// the same search `pio_add_program` makes. Returns the offset it was placed at.
+    pub fn add_program(&mut self, program: &Program) -> Result<u8, std::string::String> {
+        let offset = match program.origin {
+            Some(origin) => origin,
+            None => (0..=(IMEM_SIZE as usize).saturating_sub(program.len()) as u8)
+                .rev()
+                .find(|offset| self.can_load_program_at(program, *offset))
+                .ok_or(format!(
+                    "No room in instruction memory for program {} of {} instructions",
+                    program.name,
+                    program.len()
+                ))?,
+        };
+        self.load_program(program, offset)
+    }
+
+    /// Frees the slots of a program loaded at `offset`. Instruction memory keeps its contents.
+    /// Fails unless a program of the same length was loaded at exactly that offset.
+    pub fn remove_program(
+        &mut self,
+        program: &Program,
+        offset: u8,
+    ) -> Result<(), std::string::String> {
+        let mask = PIO::program_mask(program, offset)?;
+        if self.loaded_programs.get(&offset) != Some(&program.len()) {
+            return Err(format!(
+                "Program {} of {} instructions is not loaded at offset {}",
+                program.name,
+                program.len(),
+                offset
+            ));
+        }
+        self.loaded_programs.remove(&offset);
+        self.used_instruction_space &= !mask;
+        Ok(())
+    }
+
+    /// Sets the wrap bounds and side-set configuration of state machine `sm_id` for `program`
+    /// loaded at `offset`
+    pub fn apply_program_config(
+        &mut self,
+        sm_id: u32,
+        program: &Program,
+        offset: u8,
+    ) -> Result<(), std::string::String> {
+        let wrap_top = (offset + program.wrap) as u32 % IMEM_SIZE;
+        let wrap_bottom = (offset + program.wrap_target) as u32 % IMEM_SIZE;
+        self.mmio.sm_execctrl(sm_id)?.modify(
+            SM_EXECCTRL::WRAP_TOP.val(wrap_top)
+                + SM_EXECCTRL::WRAP_BOTTOM.val(wrap_bottom)
+                + SM_EXECCTRL::SIDE_EN.val(program.side_set.optional as u32)
+                + SM_EXECCTRL::SIDE_PINDIR.val(program.side_set.pindirs as u32),
+        );
+        self.mmio
+            .sm_pinctrl(sm_id)?
+            .modify(SM_PINCTRL::SIDESET_COUNT.val(program.side_set.bits() as u32));
+        Ok(())
+    }
+
+    /// The side-set configuration state machine `sm_id` decodes instructions with
+    pub fn side_set_config(&self, sm_id: u32) -> Result<SideSet, std::string::String> {
+        let execctrl = self.mmio.sm_execctrl(sm_id)?;
+        // SIDESET_COUNT counts the enable bit of an optional side-set, and at most 5 bits can
+        // be taken from the delay field
+        let bits = self
+            .mmio
+            .sm_pinctrl(sm_id)?
+            .read(SM_PINCTRL::SIDESET_COUNT)
+            .min(5) as u8;
+        let optional = execctrl.is_set(SM_EXECCTRL::SIDE_EN) && bits != 0;
+        Ok(SideSet {
+            count: bits - optional as u8,
+            optional,
+            pindirs: execctrl.is_set(SM_EXECCTRL::SIDE_PINDIR),
+        })
+    }
+
+    /// Lists all of instruction memory as disassembled by state machine `sm_id`, with its wrap
+    /// bounds marked and its program counter flagged with `>`
+    pub fn disassemble_memory(
+        &mut self,
+        sm_id: u32,
+    ) -> Result<std::string::String, std::string::String> {
+        let side_set = self.side_set_config(sm_id)?;
+        let execctrl = self.mmio.sm_execctrl(sm_id)?;
+        let wrap_bottom = execctrl.read(SM_EXECCTRL::WRAP_BOTTOM);
+        let wrap_top = execctrl.read(SM_EXECCTRL::WRAP_TOP);
+        let pc = self.get_sm(sm_id)?.get_pc();
+
+        let mut listing = std::string::String::new();
+        for (address, word) in (0..IMEM_SIZE).zip(self.mmio.instructions()) {
+            if address == wrap_bottom {
+                listing.push_str("            .wrap_target\n");
+            }
+            let marker = if address == pc { '>' } else { ' ' };
+            listing.push_str(&format!(
+                "{} {:2}: 0x{:04x}  {}\n",
+                marker,
+                address,
+                word,
+                instructions::disassemble_word(word, &side_set).trim_end()
+            ));
+            if address == wrap_top {
+                listing.push_str("            .wrap\n");
+            }
+        }
+        Ok(listing)
     }
 
     fn process_delay_sideset(&mut self, delay_sideset: u8) -> Result<(), std::string::String> {
@@ -430,25 +985,472 @@ impl PIO {
         Ok(())
     }
 
+    /// Mirrors live emulator state into the registers that hardware computes on read
+    fn update_debug_registers(&mut self) -> Result<(), std::string::String> {
+        let pcs = [
+            self.sm0.get_pc(),
+            self.sm1.get_pc(),
+            self.sm2.get_pc(),
+            self.sm3.get_pc(),
+        ];
+        for (sm_id, pc) in pcs.iter().enumerate() {
+            self.mmio
+                .sm_addr(sm_id as u32)?
+                .write(SM_ADDR::CURRENT_ADDRESS.val(*pc));
+            let instr_data = self.mmio.get_pc_data(*pc)?;
+            self.mmio
+                .sm_instr(sm_id as u32)?
+                .write(SM_INSTR::CUR_INSTR.val(instr_data));
+        }
+
+        let mut fstat = 0;
+        let mut flevel = 0;
+        for sm_id in 0..SM_COUNT {
+            let sm = self.get_sm(sm_id)?;
+            fstat |= (sm.tx_fifo_empty() as u32) << (FSTAT::TXEMPTY.shift as u32 + sm_id);
+            fstat |= (sm.tx_fifo_full() as u32) << (FSTAT::TXFULL.shift as u32 + sm_id);
+            fstat |= (sm.rx_fifo_empty() as u32) << (FSTAT::RXEMPTY.shift as u32 + sm_id);
+            fstat |= (sm.rx_fifo_full() as u32) << (FSTAT::RXFULL.shift as u32 + sm_id);
+            flevel |= sm.tx_fifo_level() << (sm_id * 8);
+            flevel |= sm.rx_fifo_level() << (sm_id * 8 + 4);
+        }
+        self.mmio.FSTAT.set(fstat);
+        self.mmio.FLEVEL.set(flevel);
+
+        let irq = self
+            .irq_flags
+            .iter()
+            .enumerate()
+            .fold(0, |irq, (idx, flag)| irq | ((*flag as u32 & 1) << idx));
+        self.mmio.IRQ.write(IRQ::IRQFLAGS.val(irq));
+
+        let borrowed_gpio: &gpio::GPIO = &gpio::lock(&self.gpio);
+        let pad_outputs = borrowed_gpio.get_pio_outputs(self.block_idx);
+        self.mmio
+            .DBG_PADOUT
+            .set((pad_outputs.value >> self.gpio_base) as u32);
+        self.mmio
+            .DBG_PADOE
+            .set((pad_outputs.enable >> self.gpio_base) as u32);
+
+        Ok(())
+    }
+
+    /// Maps a pin number seen by the state machine onto the physical GPIO it refers to
+    fn pio_pin(&self, pin: u32) -> usize {
+        (pin % 32 + self.gpio_base) as usize
+    }
+
+    /// GPIOs of `count` consecutive state machine pins from `base`, wrapping like the pin mapping
+    fn pio_pins(&self, base: u32, count: u32) -> u64 {
+        (0..count.min(32)).fold(0, |mask, pin| mask | 1 << self.pio_pin(base + pin))
+    }
+
+    /// GPIOs the current state machine reads or drives while executing `instr`, side-set pins
+    /// included. Reading all the pins with `mov x, pins` is left out.
+    fn instruction_pins(
+        &self,
+        instr: &PIOInstruction,
+        instr_data: u32,
+        side_set: &SideSet,
+    ) -> Result<u64, std::string::String> {
+        let pinctrl = self.mmio.sm_pinctrl(self.sm_id)?;
+        let in_base = pinctrl.read(SM_PINCTRL::IN_BASE);
+        let out_pins = self.pio_pins(
+            pinctrl.read(SM_PINCTRL::OUT_BASE),
+            pinctrl.read(SM_PINCTRL::OUT_COUNT),
+        );
+        let bit_count = |count: u8| if count == 0 { 32 } else { count as u32 };
+
+        let mut pins = match instr {
+            PIOInstruction::JMP {
+                condition: JmpCondition::BranchOnInputPin,
+                ..
+            } => {
+                let jmp_pin = self
+                    .mmio
+                    .sm_execctrl(self.sm_id)?
+                    .read(SM_EXECCTRL::JMP_PIN);
+                1 << self.pio_pin(jmp_pin)
+            }
+            PIOInstruction::WAIT {
+                source: WaitSource::GPIO,
+                index,
+                ..
+            } => 1 << self.pio_pin(*index as u32),
+            PIOInstruction::WAIT {
+                source: WaitSource::Pin,
+                index,
+                ..
+            } => 1 << self.pio_pin(*index as u32 + in_base),
+            PIOInstruction::IN {
+                source: InSource::PINS,
+                bit_count: count,
+                ..
+            } => self.pio_pins(in_base, bit_count(*count)),
+            PIOInstruction::OUT {
+                destination: OutDestination::PINS | OutDestination::PINDIRS,
+                ..
+            }
+            | PIOInstruction::MOV {
+                destination: MovDestination::PINS,
+                ..
+            } => out_pins,
+            PIOInstruction::SET {
+                destination: SetDestination::PINS | SetDestination::PINDIRS,
+                ..
+            } => self.pio_pins(
+                pinctrl.read(SM_PINCTRL::SET_BASE),
+                pinctrl.read(SM_PINCTRL::SET_COUNT),
+            ),
+            _ => 0,
+        };
+
+        let field = (instr_data >> 8) & 0x1f;
+        if side_set.count != 0 && (!side_set.optional || field & 0x10 != 0) {
+            pins |= self.pio_pins(
+                pinctrl.read(SM_PINCTRL::SIDESET_BASE),
+                side_set.count as u32,
+            );
+        }
+        Ok(pins)
+    }
+
+    fn current_driver(&self) -> gpio::GPIODriver {
+        gpio::GPIODriver::StateMachine {
+            pio: self.block_idx,
+            sm: self.sm_id as u8,
+        }
+    }
+
     fn get_current_sm(
         &mut self,
     ) -> Result<&mut state_machine::PIOStateMachine, std::string::String> {
-        match &self.sm_id {
+        self.get_sm(self.sm_id)
+    }
+
+    fn get_sm(
+        &mut self,
+        sm_id: u32,
+    ) -> Result<&mut state_machine::PIOStateMachine, std::string::String> {
+        match sm_id {
             0 => Ok(&mut self.sm0),
             1 => Ok(&mut self.sm1),
             2 => Ok(&mut self.sm2),
             3 => Ok(&mut self.sm3),
-            _ => Err(format!("Invalid State Machine ID : {}", self.sm_id)),
+            _ => Err(format!("Invalid State Machine ID : {}", sm_id)),
         }
     }
 }
 
 fn main() {
-    let gpio = Rc::new(RefCell::new(gpio::GPIO::default()));
-    let mut pio = PIO::new(0, gpio);
+    let gpio = gpio::shared(gpio::GPIO::default());
+    let mut pio = PIO::new(0, 0, gpio);
 
     pio.set_instruction_data(0, 0xffff);
 
     let run_result = pio.run();
     println!("Run result : {:?}", run_result);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FDEBUG_OFFSET: usize = 0x08;
+    const FLEVEL: usize = 0x0C;
+    const TXF0: usize = 0x10;
+    const IRQ: usize = 0x30;
+    const IRQ_FORCE: usize = 0x34;
+    const SM0_INSTR: usize = 0xD8;
+    const SM0_PINCTRL: usize = 0xDC;
+    const SM1_INSTR: usize = 0xF0;
+
+    fn new_pio() -> PIO {
+        PIO::new(0, 0, gpio::shared(gpio::GPIO::default()))
+    }
+
+    #[test]
+    fn sm_instr_follows_every_program_counter() {
+        let mut pio = new_pio();
+        pio.write_instructions(0, &[0xe021, 0x0000, 0x0000, 0xa042])
+            .unwrap();
+        pio.get_sm(1).unwrap().set_pc(3).unwrap();
+
+        assert_eq!(pio.read_register(SM0_INSTR).unwrap(), 0xe021);
+        assert_eq!(pio.read_register(SM1_INSTR).unwrap(), 0xa042);
+
+        pio.reset();
+        assert_eq!(pio.read_register(SM1_INSTR).unwrap(), 0);
+    }
+
+    #[test]
+    fn strict_access_rejects_sm_instr_writes() {
+        let mut pio = new_pio();
+        pio.set_instruction_data(0, 0xe021).unwrap();
+        pio.write_register(SM0_INSTR, 0xe041).unwrap();
+        assert_eq!(pio.read_register(SM0_INSTR).unwrap(), 0xe021);
+
+        pio.set_strict_access(true);
+        assert!(pio.write_register(SM0_INSTR, 0xe041).is_err());
+        assert!(pio.write_register(SM1_INSTR, 0xe041).is_err());
+        assert_eq!(pio.get_sm(0).unwrap().get_scratch_y(), 0);
+    }
+
+    #[test]
+    fn strict_access_leaves_full_tx_fifo_untouched() {
+        let mut pio = new_pio();
+        pio.set_strict_access(true);
+        for value in 1..=FIFO_DEPTH {
+            pio.write_register(TXF0, value).unwrap();
+        }
+
+        assert!(pio.write_register(TXF0, 0xdead_beef).is_err());
+        assert_eq!(pio.mmio.TXF0.get(), FIFO_DEPTH);
+        assert_eq!(pio.read_register(FDEBUG_OFFSET).unwrap(), 0);
+        assert_eq!(pio.get_sm(0).unwrap().tx_fifo_level(), FIFO_DEPTH);
+
+        pio.set_strict_access(false);
+        pio.write_register(TXF0, 0xdead_beef).unwrap();
+        assert_eq!(pio.read_register(FDEBUG_OFFSET).unwrap(), 1 << 16);
+    }
+
+    #[test]
+    fn register_image_and_side_file_round_trip() {
+        let mut pio = new_pio();
+        pio.write_instructions(0, &[0xe021, 0xa042]).unwrap();
+        pio.write_register(TXF0, 0x1111).unwrap();
+        pio.write_register(TXF0, 0x2222).unwrap();
+        pio.write_register(IRQ_FORCE, 0x5).unwrap();
+        pio.get_sm(2).unwrap().set_pc(1).unwrap();
+        pio.get_sm(2).unwrap().set_scratch_x(42).unwrap();
+
+        let path = std::env::temp_dir().join(format!("pio-state-{}", std::process::id()));
+        let image_path = path.with_extension("bin");
+        let state_path = path.with_extension("json");
+        pio.save_state(&image_path, Some(&state_path)).unwrap();
+
+        let mut restored = new_pio();
+        restored.load_state(&image_path, Some(&state_path)).unwrap();
+        std::fs::remove_file(&image_path).unwrap();
+        std::fs::remove_file(&state_path).unwrap();
+
+        assert_eq!(restored.snapshot().unwrap(), pio.snapshot().unwrap());
+        assert_eq!(
+            restored.export_internal_state().unwrap(),
+            pio.export_internal_state().unwrap()
+        );
+        assert_eq!(restored.get_sm(2).unwrap().get_scratch_x(), 42);
+        assert_eq!(restored.read_register(IRQ).unwrap(), 0x5);
+    }
+
+    #[test]
+    fn register_image_without_side_file_recomputes_fifo_status() {
+        let mut pio = new_pio();
+        pio.write_register(TXF0, 0x1111).unwrap();
+        let image = pio.export_register_image().unwrap();
+        assert_eq!(pio.read_register(FLEVEL).unwrap(), 1);
+
+        let mut restored = new_pio();
+        restored.import_register_image(&image).unwrap();
+        assert_eq!(restored.mmio.FLEVEL.get(), 1);
+        assert_eq!(restored.read_register(FLEVEL).unwrap(), 0);
+    }
+
+    #[test]
+    fn register_image_import_forgets_loaded_programs() {
+        let three = program("set x, 1\nset x, 2\nset x, 3");
+        let mut source = new_pio();
+        assert_eq!(source.add_program(&three), Ok(29));
+        let image = source.export_register_image().unwrap();
+
+        let mut pio = new_pio();
+        assert_eq!(pio.add_program(&three), Ok(29));
+        pio.load_program(&three, 0).unwrap();
+        pio.import_register_image(&image).unwrap();
+
+        // Only the image's code at 29 is kept from placement, and nothing can be removed
+        assert!(pio.remove_program(&three, 0).is_err());
+        assert!(pio.remove_program(&three, 29).is_err());
+        assert_eq!(pio.add_program(&three), Ok(26));
+        assert!(pio.can_load_program_at(&three, 0));
+        assert!(!pio.can_load_program_at(&three, 28));
+    }
+
+    #[test]
+    fn wait_stalls_until_stimulus_releases_it() {
+        let mut pio = new_pio();
+        // wait 1 gpio 2
+        // set x, 1
+        pio.write_instructions(0, &[0x2082, 0xe021]).unwrap();
+        gpio::lock(&pio.gpio()).add_stimulus(2, &[(3, 1)]);
+
+        pio.step_n(3).unwrap();
+        assert_eq!(pio.get_sm(0).unwrap().get_pc(), 0);
+        assert_eq!(gpio::lock(&pio.gpio()).get_cycle(), 3);
+
+        pio.step().unwrap();
+        assert_eq!(pio.get_sm(0).unwrap().get_pc(), 1);
+        pio.step().unwrap();
+        assert_eq!(pio.get_sm(0).unwrap().get_scratch_x(), 1);
+    }
+
+    #[test]
+    fn gpio_base_moves_the_pin_window() {
+        let gpio = gpio::shared(
+            gpio::GPIO::with_pin_count(gpio::RP2350B_PIN_COUNT, gpio::FunctionSelect::PIO0)
+                .unwrap(),
+        );
+        let mut pio = PIO::new(0, 0, gpio.clone());
+        assert!(pio.set_gpio_base(33).is_err());
+        pio.set_gpio_base(16).unwrap();
+
+        // set pindirs, 1
+        // set pins, 1
+        pio.write_instructions(0, &[0xe081, 0xe001]).unwrap();
+        pio.write_register(SM0_PINCTRL, 1 << 26 | 2 << 5).unwrap();
+        pio.step().unwrap();
+        pio.get_sm(0).unwrap().set_pc(1).unwrap();
+        pio.step().unwrap();
+        assert_eq!(gpio::lock(&gpio).get_level_vector(), 1 << 18);
+
+        let mut small = PIO::new(
+            0,
+            0,
+            gpio::shared(gpio::GPIO::with_pin_count(8, gpio::FunctionSelect::PIO0).unwrap()),
+        );
+        assert!(small.set_gpio_base(16).is_err());
+    }
+
+    #[test]
+    fn pio1_and_sio_reach_pins_once_funcsel_selects_them() {
+        let gpio = gpio::shared(
+            gpio::GPIO::with_pin_count(gpio::RP2040_PIN_COUNT, gpio::FunctionSelect::NULL).unwrap(),
+        );
+        let mut pio = PIO::new(1, 0, gpio.clone());
+        let mut sio = sio::SIO::new(gpio.clone());
+
+        // set pindirs, 1
+        // set pins, 1
+        pio.write_instructions(0, &[0xe081, 0xe001]).unwrap();
+        pio.write_register(SM0_PINCTRL, 1 << 26 | 2 << 5).unwrap();
+        pio.step().unwrap();
+        pio.get_sm(0).unwrap().set_pc(1).unwrap();
+        pio.step().unwrap();
+        sio.set_out(1 << 3, 1 << 3);
+        sio.set_oe(1 << 3, 1 << 3);
+        // FUNCSEL resets to NULL, so neither reaches its pin yet
+        assert_eq!(gpio::lock(&gpio).get_enable_vector(), 0);
+
+        gpio::lock(&gpio).set_function(2, gpio::FunctionSelect::PIO1);
+        gpio::lock(&gpio).set_function(3, gpio::FunctionSelect::SIO);
+        assert_eq!(gpio::lock(&gpio).get_enable_vector(), 0b1100);
+        assert_eq!(gpio::lock(&gpio).get_level_vector(), 0b1100);
+
+        gpio::lock(&gpio).set_function(2, gpio::FunctionSelect::PIO0);
+        assert_eq!(gpio::lock(&gpio).get_level_vector(), 0b1000);
+    }
+
+    #[test]
+    fn traces_name_the_pins_an_instruction_touches() {
+        let gpio = gpio::shared(gpio::GPIO::default());
+        gpio::lock(&gpio).name_pins("LED", 3).unwrap();
+        gpio::lock(&gpio).name_pins("BUS[1:0]", 4).unwrap();
+        let mut pio = PIO::new(0, 0, gpio.clone());
+        // SET 1 pin at 3, OUT 2 pins at 4, IN from 4, 1 side-set pin at 31
+        pio.write_register(
+            SM0_PINCTRL,
+            1 << 29 | 1 << 26 | 2 << 20 | 4 << 15 | 31 << 10 | 3 << 5 | 4,
+        )
+        .unwrap();
+        let side_set = pio.side_set_config(0).unwrap();
+
+        let labels = |pio: &PIO, word: u16| {
+            let instr = PIOInstruction::decode(word as u32).unwrap();
+            let pins = pio
+                .instruction_pins(&instr, word as u32, &side_set)
+                .unwrap();
+            gpio::lock(&gpio).pin_labels(pins)
+        };
+        // set pins, 1 side 0
+        assert_eq!(labels(&pio, 0xe001), "LED, GPIO31");
+        // out pins, 2 side 1
+        assert_eq!(labels(&pio, 0x7002), "BUS[0], BUS[1], GPIO31");
+        // wait 1 pin 1 side 0
+        assert_eq!(labels(&pio, 0x20a1), "BUS[1], GPIO31");
+
+        pio.write_register(SM0_PINCTRL, 0).unwrap();
+        let side_set = pio.side_set_config(0).unwrap();
+        let instr = PIOInstruction::decode(0xe021).unwrap();
+        assert_eq!(pio.instruction_pins(&instr, 0xe021, &side_set), Ok(0));
+    }
+
+    fn program(source: &str) -> Program {
+        assembler::assemble_program(source).unwrap()
+    }
+
+    #[test]
+    fn loading_relocates_jmp_targets() {
+        let mut pio = new_pio();
+        let looped = program("jmp 2\njmp !x 0\nset x, 1\njmp x-- 31");
+        assert_eq!(pio.load_program(&looped, 10), Ok(10));
+        // Targets wrap within the 5 bit address field like the SDK's relocation
+        assert_eq!(pio.instructions()[10..14], [0x000c, 0x002a, 0xe021, 0x0049]);
+        assert_eq!(pio.remove_program(&looped, 10), Ok(()));
+    }
+
+    #[test]
+    fn origin_pins_the_load_offset() {
+        let mut pio = new_pio();
+        let pinned = program(".program pinned\n.origin 4\nnop\nnop");
+        assert!(!pio.can_load_program_at(&pinned, 5));
+        assert!(pio.load_program(&pinned, 5).is_err());
+        assert_eq!(pio.add_program(&pinned), Ok(4));
+        assert!(pio.add_program(&pinned).unwrap_err().contains("overlaps"));
+    }
+
+    #[test]
+    fn overlapping_loads_are_rejected() {
+        let mut pio = new_pio();
+        let three = program("nop\nnop\nnop");
+        pio.load_program(&three, 10).unwrap();
+        assert!(!pio.can_load_program_at(&three, 12));
+        assert!(pio.load_program(&three, 12).is_err());
+        assert!(!pio.can_load_program_at(&three, 30));
+        assert_eq!(pio.load_program(&three, 13), Ok(13));
+    }
+
+    #[test]
+    fn programs_are_placed_from_the_top_of_memory() {
+        let mut pio = new_pio();
+        let three = program("nop\nnop\nnop");
+        assert_eq!(pio.add_program(&three), Ok(29));
+        assert_eq!(pio.add_program(&three), Ok(26));
+        pio.remove_program(&three, 29).unwrap();
+        assert_eq!(pio.add_program(&three), Ok(29));
+
+        let mut full = new_pio();
+        for idx in 0..10u8 {
+            assert_eq!(full.add_program(&three), Ok(29 - idx * 3));
+        }
+        assert!(full.add_program(&three).unwrap_err().contains("No room"));
+        assert_eq!(full.add_program(&program("nop\nnop")), Ok(0));
+    }
+
+    #[test]
+    fn only_loaded_programs_can_be_removed() {
+        let mut pio = new_pio();
+        let three = program("nop\nnop\nnop");
+        let two = program("nop\nnop");
+        pio.load_program(&three, 10).unwrap();
+
+        assert!(pio.remove_program(&three, 11).is_err());
+        assert!(pio.remove_program(&two, 10).is_err());
+        assert!(pio.remove_program(&two, 20).is_err());
+        assert!(!pio.can_load_program_at(&three, 10));
+
+        assert_eq!(pio.remove_program(&three, 10), Ok(()));
+        assert!(pio.remove_program(&three, 10).is_err());
+        assert!(pio.can_load_program_at(&three, 10));
+    }
+}
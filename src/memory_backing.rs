@@ -7,8 +7,15 @@ use tock_registers::registers::{ReadOnly, ReadWrite, WriteOnly};
 
 use std::fmt::Debug;
 
+use memoffset::offset_of;
+
+pub const FIFO_DEPTH: u32 = 4;
+pub const SM_COUNT: u32 = 4;
+pub const IMEM_SIZE: u32 = 32;
+
 // We are making everything `ReadWrite` so we can back this ourselves instead of only being able to use
-// it as a pointer to memory
+// it as a pointer to memory. The access the hardware actually allows is described separately by
+// `register_access` and enforced by `bus_read`/`bus_write`
 register_structs! {
     pub PIOMemoryBacking {
         (0x0 => pub CTRL: ReadWrite<u32, CTRL::Register>),
@@ -296,59 +303,198 @@ register_bitfields! {
     ],
 }
 
-impl Default for PIOMemoryBacking {
-    fn default() -> Self {
-        let backing = [0 as u8; std::mem::size_of::<PIOMemoryBacking>()];
+/// Access types as listed in the datasheet register tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterAccess {
+    /// Read-only, writes are ignored
+    RO,
+    /// Read/write
+    RW,
+    /// Write-only, reads return zero
+    WO,
+    /// Write 1 to clear
+    WC,
+    /// Self-clearing strobe, acts on the write and always reads back zero
+    SC,
+}
 
-        let pio_mem_backing: PIOMemoryBacking = unsafe { std::mem::transmute(backing) };
+/// A field of a register as listed in the datasheet, with the access the hardware allows
+#[derive(Debug, Clone, Copy)]
+pub struct FieldAccess {
+    /// `None` for registers that hold a single unnamed value, such as the FIFOs
+    pub name: Option<&'static str>,
+    /// Bits of the register the field covers, already shifted into place
+    pub mask: u32,
+    pub shift: usize,
+    pub access: RegisterAccess,
+}
 
-        pio_mem_backing
-            .FSTAT
-            .write(FSTAT::TXEMPTY.val(0xF) + FSTAT::RXEMPTY.val(0xF));
+impl FieldAccess {
+    /// Extracts the field from a register value
+    pub fn read(&self, value: u32) -> u32 {
+        (value & self.mask) >> self.shift
+    }
 
-        pio_mem_backing.SM0_CLKDIV.write(SM_CLKDIV::INT.val(1));
-        pio_mem_backing.SM1_CLKDIV.write(SM_CLKDIV::INT.val(1));
-        pio_mem_backing.SM2_CLKDIV.write(SM_CLKDIV::INT.val(1));
-        pio_mem_backing.SM3_CLKDIV.write(SM_CLKDIV::INT.val(1));
+    pub fn msb(&self) -> usize {
+        31 - self.mask.leading_zeros() as usize
+    }
+}
 
-        pio_mem_backing
-            .SM0_EXECCTRL
-            .write(SM_EXECCTRL::WRAP_TOP.val(0x1f));
-        pio_mem_backing
-            .SM1_EXECCTRL
-            .write(SM_EXECCTRL::WRAP_TOP.val(0x1f));
-        pio_mem_backing
-            .SM2_EXECCTRL
-            .write(SM_EXECCTRL::WRAP_TOP.val(0x1f));
-        pio_mem_backing
-            .SM3_EXECCTRL
-            .write(SM_EXECCTRL::WRAP_TOP.val(0x1f));
+macro_rules! field_access {
+    ($register:ident, $field:ident, $access:ident) => {
+        FieldAccess {
+            name: Some(stringify!($field)),
+            mask: $register::$field.mask << $register::$field.shift,
+            shift: $register::$field.shift,
+            access: RegisterAccess::$access,
+        }
+    };
+    (mask $mask:expr, $access:ident) => {
+        FieldAccess {
+            name: None,
+            mask: $mask,
+            shift: 0,
+            access: RegisterAccess::$access,
+        }
+    };
+}
 
-        pio_mem_backing
-            .SM0_SHIFTCTRL
-            .write(SM_SHIFTCTRL::IN_SHIFTDIR.val(1) + SM_SHIFTCTRL::OUT_SHIFTDIR.val(1));
-        pio_mem_backing
-            .SM1_SHIFTCTRL
-            .write(SM_SHIFTCTRL::IN_SHIFTDIR.val(1) + SM_SHIFTCTRL::OUT_SHIFTDIR.val(1));
-        pio_mem_backing
-            .SM2_SHIFTCTRL
-            .write(SM_SHIFTCTRL::IN_SHIFTDIR.val(1) + SM_SHIFTCTRL::OUT_SHIFTDIR.val(1));
-        pio_mem_backing
-            .SM3_SHIFTCTRL
-            .write(SM_SHIFTCTRL::IN_SHIFTDIR.val(1) + SM_SHIFTCTRL::OUT_SHIFTDIR.val(1));
+// INTR, IRQ0_* and IRQ1_* all share the same layout
+macro_rules! interrupt_access {
+    ($access:ident) => {
+        [
+            field_access!(INTR, SM3, $access),
+            field_access!(INTR, SM2, $access),
+            field_access!(INTR, SM1, $access),
+            field_access!(INTR, SM0, $access),
+            field_access!(INTR, SM3_TXNFULL, $access),
+            field_access!(INTR, SM2_TXNFULL, $access),
+            field_access!(INTR, SM1_TXNFULL, $access),
+            field_access!(INTR, SM0_TXNFULL, $access),
+            field_access!(INTR, SM3_RXNEMPTY, $access),
+            field_access!(INTR, SM2_RXNEMPTY, $access),
+            field_access!(INTR, SM1_RXNEMPTY, $access),
+            field_access!(INTR, SM0_RXNEMPTY, $access),
+        ]
+    };
+}
 
-        pio_mem_backing
-            .SM0_PINCTRL
-            .write(SM_PINCTRL::SET_COUNT.val(5));
-        pio_mem_backing
-            .SM1_PINCTRL
-            .write(SM_PINCTRL::SET_COUNT.val(5));
-        pio_mem_backing
-            .SM2_PINCTRL
-            .write(SM_PINCTRL::SET_COUNT.val(5));
-        pio_mem_backing
-            .SM3_PINCTRL
-            .write(SM_PINCTRL::SET_COUNT.val(5));
+const CTRL_ACCESS: [FieldAccess; 3] = [
+    field_access!(CTRL, CLKDIV_RESTART, SC),
+    field_access!(CTRL, SM_RESTART, SC),
+    field_access!(CTRL, SM_ENABLE, RW),
+];
+const FSTAT_ACCESS: [FieldAccess; 4] = [
+    field_access!(FSTAT, TXEMPTY, RO),
+    field_access!(FSTAT, TXFULL, RO),
+    field_access!(FSTAT, RXEMPTY, RO),
+    field_access!(FSTAT, RXFULL, RO),
+];
+const FDEBUG_ACCESS: [FieldAccess; 4] = [
+    field_access!(FDEBUG, TXSTALL, WC),
+    field_access!(FDEBUG, TXOVER, WC),
+    field_access!(FDEBUG, RXUNDER, WC),
+    field_access!(FDEBUG, RXSTALL, WC),
+];
+const FLEVEL_ACCESS: [FieldAccess; 8] = [
+    field_access!(FLEVEL, RX3, RO),
+    field_access!(FLEVEL, TX3, RO),
+    field_access!(FLEVEL, RX2, RO),
+    field_access!(FLEVEL, TX2, RO),
+    field_access!(FLEVEL, RX1, RO),
+    field_access!(FLEVEL, TX1, RO),
+    field_access!(FLEVEL, RX0, RO),
+    field_access!(FLEVEL, TX0, RO),
+];
+const TXF_ACCESS: [FieldAccess; 1] = [field_access!(mask 0xFFFF_FFFF, WO)];
+const RXF_ACCESS: [FieldAccess; 1] = [field_access!(mask 0xFFFF_FFFF, RO)];
+const IRQ_ACCESS: [FieldAccess; 1] = [field_access!(IRQ, IRQFLAGS, WC)];
+const IRQ_FORCE_ACCESS: [FieldAccess; 1] = [field_access!(IRQ_FORCE, IRQFLAGS, WO)];
+const INPUT_SYNC_BYPASS_ACCESS: [FieldAccess; 1] = [field_access!(mask 0xFFFF_FFFF, RW)];
+const DBG_PAD_ACCESS: [FieldAccess; 1] = [field_access!(mask 0xFFFF_FFFF, RO)];
+const DBG_CFGINFO_ACCESS: [FieldAccess; 3] = [
+    field_access!(DBG_CFGINFO, IMEM_SIZE, RO),
+    field_access!(DBG_CFGINFO, SM_COUNT, RO),
+    field_access!(DBG_CFGINFO, FIFO_DEPTH, RO),
+];
+const INSTR_MEM_ACCESS: [FieldAccess; 1] = [field_access!(mask 0xFFFF, WO)];
+const SM_CLKDIV_ACCESS: [FieldAccess; 2] = [
+    field_access!(SM_CLKDIV, INT, RW),
+    field_access!(SM_CLKDIV, FRAC, RW),
+];
+const SM_EXECCTRL_ACCESS: [FieldAccess; 11] = [
+    field_access!(SM_EXECCTRL, EXECSTALLED, RO),
+    field_access!(SM_EXECCTRL, SIDE_EN, RW),
+    field_access!(SM_EXECCTRL, SIDE_PINDIR, RW),
+    field_access!(SM_EXECCTRL, JMP_PIN, RW),
+    field_access!(SM_EXECCTRL, OUT_EN_SEL, RW),
+    field_access!(SM_EXECCTRL, INLINE_OUT_SEL, RW),
+    field_access!(SM_EXECCTRL, OUT_STICKY, RW),
+    field_access!(SM_EXECCTRL, WRAP_TOP, RW),
+    field_access!(SM_EXECCTRL, WRAP_BOTTOM, RW),
+    field_access!(SM_EXECCTRL, STATUS_SEL, RW),
+    field_access!(SM_EXECCTRL, STATUS_N, RW),
+];
+const SM_SHIFTCTRL_ACCESS: [FieldAccess; 8] = [
+    field_access!(SM_SHIFTCTRL, FJOIN_RX, RW),
+    field_access!(SM_SHIFTCTRL, FJOIN_TX, RW),
+    field_access!(SM_SHIFTCTRL, PULL_THRESH, RW),
+    field_access!(SM_SHIFTCTRL, PUSH_THRESH, RW),
+    field_access!(SM_SHIFTCTRL, OUT_SHIFTDIR, RW),
+    field_access!(SM_SHIFTCTRL, IN_SHIFTDIR, RW),
+    field_access!(SM_SHIFTCTRL, AUTOPULL, RW),
+    field_access!(SM_SHIFTCTRL, AUTOPUSH, RW),
+];
+const SM_ADDR_ACCESS: [FieldAccess; 1] = [field_access!(SM_ADDR, CURRENT_ADDRESS, RO)];
+const SM_INSTR_ACCESS: [FieldAccess; 1] = [field_access!(SM_INSTR, CUR_INSTR, RW)];
+const SM_PINCTRL_ACCESS: [FieldAccess; 7] = [
+    field_access!(SM_PINCTRL, SIDESET_COUNT, RW),
+    field_access!(SM_PINCTRL, SET_COUNT, RW),
+    field_access!(SM_PINCTRL, OUT_COUNT, RW),
+    field_access!(SM_PINCTRL, IN_BASE, RW),
+    field_access!(SM_PINCTRL, SIDESET_BASE, RW),
+    field_access!(SM_PINCTRL, SET_BASE, RW),
+    field_access!(SM_PINCTRL, OUT_BASE, RW),
+];
+const INTERRUPT_RW_ACCESS: [FieldAccess; 12] = interrupt_access!(RW);
+const INTERRUPT_RO_ACCESS: [FieldAccess; 12] = interrupt_access!(RO);
+
+/// Returns the per-field access policy of the register at `offset`. Bits that are not covered by
+/// any field are reserved.
+pub fn register_access(offset: usize) -> Result<&'static [FieldAccess], std::string::String> {
+    match offset {
+        0x0 => Ok(&CTRL_ACCESS),
+        0x4 => Ok(&FSTAT_ACCESS),
+        0x8 => Ok(&FDEBUG_ACCESS),
+        0xC => Ok(&FLEVEL_ACCESS),
+        0x10 | 0x14 | 0x18 | 0x1C => Ok(&TXF_ACCESS),
+        0x20 | 0x24 | 0x28 | 0x2C => Ok(&RXF_ACCESS),
+        0x30 => Ok(&IRQ_ACCESS),
+        0x34 => Ok(&IRQ_FORCE_ACCESS),
+        0x38 => Ok(&INPUT_SYNC_BYPASS_ACCESS),
+        0x3C | 0x40 => Ok(&DBG_PAD_ACCESS),
+        0x44 => Ok(&DBG_CFGINFO_ACCESS),
+        0x48..=0xC4 if offset.is_multiple_of(4) => Ok(&INSTR_MEM_ACCESS),
+        0xC8..=0x124 if offset.is_multiple_of(4) => match (offset - 0xC8) % 0x18 {
+            0x0 => Ok(&SM_CLKDIV_ACCESS),
+            0x4 => Ok(&SM_EXECCTRL_ACCESS),
+            0x8 => Ok(&SM_SHIFTCTRL_ACCESS),
+            0xC => Ok(&SM_ADDR_ACCESS),
+            0x10 => Ok(&SM_INSTR_ACCESS),
+            _ => Ok(&SM_PINCTRL_ACCESS),
+        },
+        0x128 | 0x134 | 0x140 => Ok(&INTERRUPT_RO_ACCESS),
+        0x12C | 0x130 | 0x138 | 0x13C => Ok(&INTERRUPT_RW_ACCESS),
+        _ => Err(format!("Invalid register offset : 0x{:X}", offset)),
+    }
+}
+
+impl Default for PIOMemoryBacking {
+    fn default() -> Self {
+        let backing = [0 as u8; std::mem::size_of::<PIOMemoryBacking>()];
+
+        let pio_mem_backing: PIOMemoryBacking = unsafe { std::mem::transmute(backing) };
+        pio_mem_backing.reset();
 
         pio_mem_backing
     }
@@ -443,179 +589,434 @@ impl Debug for PIOMemoryBacking {
 }
 
 impl PIOMemoryBacking {
-    pub fn get_pc_data(&self, pc: u32) -> Result<u32, std::string::String> {
-        match pc {
-            0 => Ok(self.INSTR_MEM0.get()),
-            1 => Ok(self.INSTR_MEM1.get()),
-            2 => Ok(self.INSTR_MEM2.get()),
-            3 => Ok(self.INSTR_MEM3.get()),
-            4 => Ok(self.INSTR_MEM4.get()),
-            5 => Ok(self.INSTR_MEM5.get()),
-            6 => Ok(self.INSTR_MEM6.get()),
-            7 => Ok(self.INSTR_MEM7.get()),
-            8 => Ok(self.INSTR_MEM8.get()),
-            9 => Ok(self.INSTR_MEM9.get()),
-            10 => Ok(self.INSTR_MEM10.get()),
-            11 => Ok(self.INSTR_MEM11.get()),
-            12 => Ok(self.INSTR_MEM12.get()),
-            13 => Ok(self.INSTR_MEM13.get()),
-            14 => Ok(self.INSTR_MEM14.get()),
-            15 => Ok(self.INSTR_MEM15.get()),
-            16 => Ok(self.INSTR_MEM16.get()),
-            17 => Ok(self.INSTR_MEM17.get()),
-            18 => Ok(self.INSTR_MEM18.get()),
-            19 => Ok(self.INSTR_MEM19.get()),
-            20 => Ok(self.INSTR_MEM20.get()),
-            21 => Ok(self.INSTR_MEM21.get()),
-            22 => Ok(self.INSTR_MEM22.get()),
-            23 => Ok(self.INSTR_MEM23.get()),
-            24 => Ok(self.INSTR_MEM24.get()),
-            25 => Ok(self.INSTR_MEM25.get()),
-            26 => Ok(self.INSTR_MEM26.get()),
-            27 => Ok(self.INSTR_MEM27.get()),
-            28 => Ok(self.INSTR_MEM28.get()),
-            29 => Ok(self.INSTR_MEM29.get()),
-            30 => Ok(self.INSTR_MEM30.get()),
-            31 => Ok(self.INSTR_MEM31.get()),
-            _ => Err(format!("Invalid PC provided : {}", pc)),
+    /// Restores every register to its datasheet reset value
+    pub fn reset(&self) {
+        for register in self.registers() {
+            register.set(0);
+        }
+
+        self.FSTAT
+            .write(FSTAT::TXEMPTY.val(0xF) + FSTAT::RXEMPTY.val(0xF));
+
+        self.DBG_CFGINFO.write(
+            DBG_CFGINFO::IMEM_SIZE.val(IMEM_SIZE)
+                + DBG_CFGINFO::SM_COUNT.val(SM_COUNT)
+                + DBG_CFGINFO::FIFO_DEPTH.val(FIFO_DEPTH),
+        );
+
+        for sm_id in 0..SM_COUNT {
+            // The SM register accessors only fail for IDs outside 0..SM_COUNT
+            self.sm_clkdiv(sm_id).unwrap().write(SM_CLKDIV::INT.val(1));
+            self.sm_execctrl(sm_id)
+                .unwrap()
+                .write(SM_EXECCTRL::WRAP_TOP.val(0x1f));
+            self.sm_shiftctrl(sm_id)
+                .unwrap()
+                .write(SM_SHIFTCTRL::IN_SHIFTDIR.val(1) + SM_SHIFTCTRL::OUT_SHIFTDIR.val(1));
+            self.sm_pinctrl(sm_id)
+                .unwrap()
+                .write(SM_PINCTRL::SET_COUNT.val(5));
         }
     }
 
-    pub fn set_instruction_data(
-        &mut self,
-        index: u8,
-        data: u16,
-    ) -> Result<(), std::string::String> {
-        match index {
-            0 => {
-                self.INSTR_MEM0.set(data as u32);
-                Ok(())
-            }
-            1 => {
-                self.INSTR_MEM1.set(data as u32);
-                Ok(())
-            }
-            2 => {
-                self.INSTR_MEM2.set(data as u32);
-                Ok(())
-            }
-            3 => {
-                self.INSTR_MEM3.set(data as u32);
-                Ok(())
-            }
-            4 => {
-                self.INSTR_MEM4.set(data as u32);
-                Ok(())
-            }
-            5 => {
-                self.INSTR_MEM5.set(data as u32);
-                Ok(())
-            }
-            6 => {
-                self.INSTR_MEM6.set(data as u32);
-                Ok(())
-            }
-            7 => {
-                self.INSTR_MEM7.set(data as u32);
-                Ok(())
-            }
-            8 => {
-                self.INSTR_MEM8.set(data as u32);
-                Ok(())
-            }
-            9 => {
-                self.INSTR_MEM9.set(data as u32);
-                Ok(())
-            }
-            10 => {
-                self.INSTR_MEM10.set(data as u32);
-                Ok(())
-            }
-            11 => {
-                self.INSTR_MEM11.set(data as u32);
-                Ok(())
-            }
-            12 => {
-                self.INSTR_MEM12.set(data as u32);
-                Ok(())
-            }
-            13 => {
-                self.INSTR_MEM13.set(data as u32);
-                Ok(())
-            }
-            14 => {
-                self.INSTR_MEM14.set(data as u32);
-                Ok(())
-            }
-            15 => {
-                self.INSTR_MEM15.set(data as u32);
-                Ok(())
-            }
-            16 => {
-                self.INSTR_MEM16.set(data as u32);
-                Ok(())
-            }
-            17 => {
-                self.INSTR_MEM17.set(data as u32);
-                Ok(())
-            }
-            18 => {
-                self.INSTR_MEM18.set(data as u32);
-                Ok(())
-            }
-            19 => {
-                self.INSTR_MEM19.set(data as u32);
-                Ok(())
-            }
-            20 => {
-                self.INSTR_MEM20.set(data as u32);
-                Ok(())
-            }
-            21 => {
-                self.INSTR_MEM21.set(data as u32);
-                Ok(())
-            }
-            22 => {
-                self.INSTR_MEM22.set(data as u32);
-                Ok(())
-            }
-            23 => {
-                self.INSTR_MEM23.set(data as u32);
-                Ok(())
-            }
-            24 => {
-                self.INSTR_MEM24.set(data as u32);
-                Ok(())
-            }
-            25 => {
-                self.INSTR_MEM25.set(data as u32);
-                Ok(())
-            }
-            26 => {
-                self.INSTR_MEM26.set(data as u32);
-                Ok(())
-            }
-            27 => {
-                self.INSTR_MEM27.set(data as u32);
-                Ok(())
-            }
-            28 => {
-                self.INSTR_MEM28.set(data as u32);
-                Ok(())
-            }
-            29 => {
-                self.INSTR_MEM29.set(data as u32);
-                Ok(())
-            }
-            30 => {
-                self.INSTR_MEM30.set(data as u32);
-                Ok(())
-            }
-            31 => {
-                self.INSTR_MEM31.set(data as u32);
-                Ok(())
+    pub fn read_offset(&self, offset: usize) -> Result<u32, std::string::String> {
+        if !offset.is_multiple_of(4) || offset >= std::mem::size_of::<PIOMemoryBacking>() {
+            return Err(format!("Invalid register offset : 0x{:X}", offset));
+        }
+
+        Ok(self.registers()[offset / 4].get())
+    }
+
+    /// Reads a register the way the system bus sees it: write-only and self-clearing fields read
+    /// back as zero
+    pub fn bus_read(&self, offset: usize) -> Result<u32, std::string::String> {
+        let value = self.read_offset(offset)?;
+        let readable = register_access(offset)?
+            .iter()
+            .filter(|field| {
+                field.access != RegisterAccess::WO && field.access != RegisterAccess::SC
+            })
+            .fold(0, |mask, field| mask | field.mask);
+
+        Ok(value & readable)
+    }
+
+    /// Applies a system bus write to the register at `offset` according to its access policy.
+    /// Writes to read-only fields and reserved bits are dropped like the hardware does, or
+    /// reported as errors when `strict` is set. Returns the bits written to self-clearing fields so
+    /// the caller can act on the strobe.
+    pub fn bus_write(
+        &self,
+        offset: usize,
+        value: u32,
+        strict: bool,
+    ) -> Result<u32, std::string::String> {
+        let policy = register_access(offset)?;
+        let current = self.read_offset(offset)?;
+
+        let defined = policy.iter().fold(0, |mask, field| mask | field.mask);
+        if strict && value & !defined != 0 {
+            return Err(format!(
+                "Write of 0x{:08X} to reserved bits 0x{:08X} of register 0x{:X}",
+                value,
+                value & !defined,
+                offset
+            ));
+        }
+
+        let mut next = current;
+        let mut strobes = 0;
+        for field in policy {
+            let written = value & field.mask;
+            match field.access {
+                RegisterAccess::RW | RegisterAccess::WO => next = (next & !field.mask) | written,
+                RegisterAccess::WC => next &= !written,
+                RegisterAccess::SC => strobes |= written,
+                RegisterAccess::RO => {
+                    if strict && written != current & field.mask {
+                        return Err(format!(
+                            "Write of 0x{:08X} to read-only bits 0x{:08X} of register 0x{:X}",
+                            value, field.mask, offset
+                        ));
+                    }
+                }
             }
-            _ => Err(format!("Invalid index provided : {}", index)),
         }
+
+        self.registers()[offset / 4].set(next);
+        Ok(strobes)
+    }
+
+    /// Serializes the register block as the raw little-endian image a debug probe reads back
+    pub fn to_image(&self) -> Vec<u8> {
+        self.registers()
+            .iter()
+            .flat_map(|register| register.get().to_le_bytes().to_vec())
+            .collect()
+    }
+
+    /// Overwrites every register from a raw little-endian image, bypassing the access policy
+    pub fn load_image(&self, image: &[u8]) -> Result<(), std::string::String> {
+        if image.len() != std::mem::size_of::<PIOMemoryBacking>() {
+            return Err(format!(
+                "Invalid register image length : 0x{:X}, expected 0x{:X}",
+                image.len(),
+                std::mem::size_of::<PIOMemoryBacking>()
+            ));
+        }
+
+        for (register, bytes) in self.registers().iter().zip(image.chunks_exact(4)) {
+            register.set(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+        }
+        Ok(())
+    }
+
+    fn registers(&self) -> &[ReadWrite<u32>] {
+        // Every register in the block is a `ReadWrite<u32>` laid out back to back by
+        // `register_structs!`, so the block can be viewed as an array of them
+        unsafe {
+            std::slice::from_raw_parts(
+                self as *const PIOMemoryBacking as *const ReadWrite<u32>,
+                std::mem::size_of::<PIOMemoryBacking>() / 4,
+            )
+        }
+    }
+
+    pub fn sm_clkdiv(
+        &self,
+        sm_id: u32,
+    ) -> Result<&ReadWrite<u32, SM_CLKDIV::Register>, std::string::String> {
+        match sm_id {
+            0 => Ok(&self.SM0_CLKDIV),
+            1 => Ok(&self.SM1_CLKDIV),
+            2 => Ok(&self.SM2_CLKDIV),
+            3 => Ok(&self.SM3_CLKDIV),
+            _ => Err(format!("Invalid State Machine ID : {}", sm_id)),
+        }
+    }
+
+    pub fn sm_execctrl(
+        &self,
+        sm_id: u32,
+    ) -> Result<&ReadWrite<u32, SM_EXECCTRL::Register>, std::string::String> {
+        match sm_id {
+            0 => Ok(&self.SM0_EXECCTRL),
+            1 => Ok(&self.SM1_EXECCTRL),
+            2 => Ok(&self.SM2_EXECCTRL),
+            3 => Ok(&self.SM3_EXECCTRL),
+            _ => Err(format!("Invalid State Machine ID : {}", sm_id)),
+        }
+    }
+
+    pub fn sm_shiftctrl(
+        &self,
+        sm_id: u32,
+    ) -> Result<&ReadWrite<u32, SM_SHIFTCTRL::Register>, std::string::String> {
+        match sm_id {
+            0 => Ok(&self.SM0_SHIFTCTRL),
+            1 => Ok(&self.SM1_SHIFTCTRL),
+            2 => Ok(&self.SM2_SHIFTCTRL),
+            3 => Ok(&self.SM3_SHIFTCTRL),
+            _ => Err(format!("Invalid State Machine ID : {}", sm_id)),
+        }
+    }
+
+    pub fn sm_addr(
+        &self,
+        sm_id: u32,
+    ) -> Result<&ReadWrite<u32, SM_ADDR::Register>, std::string::String> {
+        match sm_id {
+            0 => Ok(&self.SM0_ADDR),
+            1 => Ok(&self.SM1_ADDR),
+            2 => Ok(&self.SM2_ADDR),
+            3 => Ok(&self.SM3_ADDR),
+            _ => Err(format!("Invalid State Machine ID : {}", sm_id)),
+        }
+    }
+
+    pub fn sm_instr(
+        &self,
+        sm_id: u32,
+    ) -> Result<&ReadWrite<u32, SM_INSTR::Register>, std::string::String> {
+        match sm_id {
+            0 => Ok(&self.SM0_INSTR),
+            1 => Ok(&self.SM1_INSTR),
+            2 => Ok(&self.SM2_INSTR),
+            3 => Ok(&self.SM3_INSTR),
+            _ => Err(format!("Invalid State Machine ID : {}", sm_id)),
+        }
+    }
+
+    pub fn sm_pinctrl(
+        &self,
+        sm_id: u32,
+    ) -> Result<&ReadWrite<u32, SM_PINCTRL::Register>, std::string::String> {
+        match sm_id {
+            0 => Ok(&self.SM0_PINCTRL),
+            1 => Ok(&self.SM1_PINCTRL),
+            2 => Ok(&self.SM2_PINCTRL),
+            3 => Ok(&self.SM3_PINCTRL),
+            _ => Err(format!("Invalid State Machine ID : {}", sm_id)),
+        }
+    }
+
+    /// Instruction memory as a slice of its `INSTR_MEM` registers, indexed by address
+    pub fn instruction_memory(&self) -> &[ReadWrite<u32>] {
+        let start = offset_of!(PIOMemoryBacking, INSTR_MEM0) / 4;
+        &self.registers()[start..start + IMEM_SIZE as usize]
+    }
+
+    /// Every instruction word, by address
+    pub fn instructions(&self) -> impl Iterator<Item = u16> + '_ {
+        self.instruction_memory()
+            .iter()
+            .map(|register| register.get() as u16)
+    }
+
+    pub fn get_pc_data(&self, pc: u32) -> Result<u32, std::string::String> {
+        self.instruction_memory()
+            .get(pc as usize)
+            .map(|register| register.get())
+            .ok_or(format!("Invalid PC provided : {}", pc))
+    }
+
+    pub fn set_instruction_data(&self, index: u8, data: u16) -> Result<(), std::string::String> {
+        self.write_instructions(index, &[data])
+    }
+
+    /// `count` words starting at address `start`
+    pub fn read_instructions(
+        &self,
+        start: u8,
+        count: usize,
+    ) -> Result<Vec<u16>, std::string::String> {
+        let registers = self
+            .instruction_memory()
+            .get(start as usize..start as usize + count)
+            .ok_or(format!(
+                "Invalid instruction range : {} words at {}",
+                count, start
+            ))?;
+        Ok(registers
+            .iter()
+            .map(|register| register.get() as u16)
+            .collect())
+    }
+
+    /// Writes `words` to consecutive addresses starting at `start`
+    pub fn write_instructions(&self, start: u8, words: &[u16]) -> Result<(), std::string::String> {
+        let registers = self
+            .instruction_memory()
+            .get(start as usize..start as usize + words.len())
+            .ok_or(format!(
+                "Invalid instruction index : {} words at {}",
+                words.len(),
+                start
+            ))?;
+        for (register, word) in registers.iter().zip(words) {
+            register.set(*word as u32);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reset values from the RP2040 datasheet, section 3.7 "List of Registers"
+    const RESET_VALUES: [(usize, &str, u32); 81] = [
+        (0x000, "CTRL", 0x0000_0000),
+        (0x004, "FSTAT", 0x0f00_0f00),
+        (0x008, "FDEBUG", 0x0000_0000),
+        (0x00C, "FLEVEL", 0x0000_0000),
+        (0x010, "TXF0", 0x0000_0000),
+        (0x014, "TXF1", 0x0000_0000),
+        (0x018, "TXF2", 0x0000_0000),
+        (0x01C, "TXF3", 0x0000_0000),
+        (0x020, "RXF0", 0x0000_0000),
+        (0x024, "RXF1", 0x0000_0000),
+        (0x028, "RXF2", 0x0000_0000),
+        (0x02C, "RXF3", 0x0000_0000),
+        (0x030, "IRQ", 0x0000_0000),
+        (0x034, "IRQ_FORCE", 0x0000_0000),
+        (0x038, "INPUT_SYNC_BYPASS", 0x0000_0000),
+        (0x03C, "DBG_PADOUT", 0x0000_0000),
+        (0x040, "DBG_PADOE", 0x0000_0000),
+        (0x044, "DBG_CFGINFO", 0x0020_0404),
+        (0x048, "INSTR_MEM0", 0x0000_0000),
+        (0x04C, "INSTR_MEM1", 0x0000_0000),
+        (0x050, "INSTR_MEM2", 0x0000_0000),
+        (0x054, "INSTR_MEM3", 0x0000_0000),
+        (0x058, "INSTR_MEM4", 0x0000_0000),
+        (0x05C, "INSTR_MEM5", 0x0000_0000),
+        (0x060, "INSTR_MEM6", 0x0000_0000),
+        (0x064, "INSTR_MEM7", 0x0000_0000),
+        (0x068, "INSTR_MEM8", 0x0000_0000),
+        (0x06C, "INSTR_MEM9", 0x0000_0000),
+        (0x070, "INSTR_MEM10", 0x0000_0000),
+        (0x074, "INSTR_MEM11", 0x0000_0000),
+        (0x078, "INSTR_MEM12", 0x0000_0000),
+        (0x07C, "INSTR_MEM13", 0x0000_0000),
+        (0x080, "INSTR_MEM14", 0x0000_0000),
+        (0x084, "INSTR_MEM15", 0x0000_0000),
+        (0x088, "INSTR_MEM16", 0x0000_0000),
+        (0x08C, "INSTR_MEM17", 0x0000_0000),
+        (0x090, "INSTR_MEM18", 0x0000_0000),
+        (0x094, "INSTR_MEM19", 0x0000_0000),
+        (0x098, "INSTR_MEM20", 0x0000_0000),
+        (0x09C, "INSTR_MEM21", 0x0000_0000),
+        (0x0A0, "INSTR_MEM22", 0x0000_0000),
+        (0x0A4, "INSTR_MEM23", 0x0000_0000),
+        (0x0A8, "INSTR_MEM24", 0x0000_0000),
+        (0x0AC, "INSTR_MEM25", 0x0000_0000),
+        (0x0B0, "INSTR_MEM26", 0x0000_0000),
+        (0x0B4, "INSTR_MEM27", 0x0000_0000),
+        (0x0B8, "INSTR_MEM28", 0x0000_0000),
+        (0x0BC, "INSTR_MEM29", 0x0000_0000),
+        (0x0C0, "INSTR_MEM30", 0x0000_0000),
+        (0x0C4, "INSTR_MEM31", 0x0000_0000),
+        (0x0C8, "SM0_CLKDIV", 0x0001_0000),
+        (0x0CC, "SM0_EXECCTRL", 0x0001_f000),
+        (0x0D0, "SM0_SHIFTCTRL", 0x000c_0000),
+        (0x0D4, "SM0_ADDR", 0x0000_0000),
+        (0x0D8, "SM0_INSTR", 0x0000_0000),
+        (0x0DC, "SM0_PINCTRL", 0x1400_0000),
+        (0x0E0, "SM1_CLKDIV", 0x0001_0000),
+        (0x0E4, "SM1_EXECCTRL", 0x0001_f000),
+        (0x0E8, "SM1_SHIFTCTRL", 0x000c_0000),
+        (0x0EC, "SM1_ADDR", 0x0000_0000),
+        (0x0F0, "SM1_INSTR", 0x0000_0000),
+        (0x0F4, "SM1_PINCTRL", 0x1400_0000),
+        (0x0F8, "SM2_CLKDIV", 0x0001_0000),
+        (0x0FC, "SM2_EXECCTRL", 0x0001_f000),
+        (0x100, "SM2_SHIFTCTRL", 0x000c_0000),
+        (0x104, "SM2_ADDR", 0x0000_0000),
+        (0x108, "SM2_INSTR", 0x0000_0000),
+        (0x10C, "SM2_PINCTRL", 0x1400_0000),
+        (0x110, "SM3_CLKDIV", 0x0001_0000),
+        (0x114, "SM3_EXECCTRL", 0x0001_f000),
+        (0x118, "SM3_SHIFTCTRL", 0x000c_0000),
+        (0x11C, "SM3_ADDR", 0x0000_0000),
+        (0x120, "SM3_INSTR", 0x0000_0000),
+        (0x124, "SM3_PINCTRL", 0x1400_0000),
+        (0x128, "INTR", 0x0000_0000),
+        (0x12C, "IRQ0_INTE", 0x0000_0000),
+        (0x130, "IRQ0_INTF", 0x0000_0000),
+        (0x134, "IRQ0_INTS", 0x0000_0000),
+        (0x138, "IRQ1_INTE", 0x0000_0000),
+        (0x13C, "IRQ1_INTF", 0x0000_0000),
+        (0x140, "IRQ1_INTS", 0x0000_0000),
+    ];
+
+    fn assert_reset_values(backing: &PIOMemoryBacking) {
+        for (offset, name, value) in RESET_VALUES.iter() {
+            assert_eq!(
+                backing.read_offset(*offset).unwrap(),
+                *value,
+                "{} (0x{:03X}) does not match its reset value",
+                name,
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn default_matches_datasheet_reset_values() {
+        assert_reset_values(&PIOMemoryBacking::default());
+    }
+
+    #[test]
+    fn reset_restores_datasheet_reset_values() {
+        let backing = PIOMemoryBacking::default();
+        for offset in (0..std::mem::size_of::<PIOMemoryBacking>()).step_by(4) {
+            backing.registers()[offset / 4].set(0xdead_beef);
+        }
+
+        backing.reset();
+        assert_reset_values(&backing);
+    }
+
+    #[test]
+    fn strict_bus_write_rejects_reserved_and_read_only_bits() {
+        let backing = PIOMemoryBacking::default();
+
+        // CTRL has nothing above CLKDIV_RESTART
+        assert!(backing.bus_write(0x000, 0x0000_1001, true).is_err());
+        assert_eq!(backing.read_offset(0x000).unwrap(), 0);
+        assert_eq!(backing.bus_write(0x000, 0x0000_1001, false).unwrap(), 0);
+        assert_eq!(backing.read_offset(0x000).unwrap(), 0x1);
+
+        assert!(backing.bus_write(0x004, 0, true).is_err());
+        backing.bus_write(0x004, 0, false).unwrap();
+        assert_eq!(backing.read_offset(0x004).unwrap(), 0x0f00_0f00);
+    }
+
+    #[test]
+    fn bus_write_clears_wc_bits_and_returns_sc_strobes() {
+        let backing = PIOMemoryBacking::default();
+        backing.FDEBUG.set(0x0101_0101);
+        assert_eq!(backing.bus_write(0x008, 0x0000_0100, true).unwrap(), 0);
+        assert_eq!(backing.read_offset(0x008).unwrap(), 0x0101_0001);
+
+        let strobes = backing.bus_write(0x000, 0x0000_0321, true).unwrap();
+        assert_eq!(strobes, 0x0000_0320);
+        assert_eq!(backing.bus_read(0x000).unwrap(), 0x1);
+    }
+
+    #[test]
+    fn instruction_memory_maps_instr_mem_registers() {
+        let backing = PIOMemoryBacking::default();
+        backing.write_instructions(30, &[0xe001, 0x0000]).unwrap();
+        backing.set_instruction_data(0, 0xa042).unwrap();
+
+        assert_eq!(backing.read_offset(0x048).unwrap(), 0xa042);
+        assert_eq!(backing.read_offset(0x0C0).unwrap(), 0xe001);
+        assert_eq!(backing.instruction_memory().len(), IMEM_SIZE as usize);
+        assert_eq!(backing.read_instructions(29, 2).unwrap(), vec![0, 0xe001]);
+        assert_eq!(backing.instructions().nth(30), Some(0xe001));
+        assert!(backing.write_instructions(31, &[0, 0]).is_err());
+        assert!(backing.read_instructions(0, 33).is_err());
+        assert!(backing.get_pc_data(IMEM_SIZE).is_err());
     }
 }
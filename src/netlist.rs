@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::VecDeque;
+use std::path::Path;
+
+use crate::gpio::{GPIODriver, GPIO};
+
+/// A wire carrying the pad level of `from` to every pin in `to`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Net {
+    pub name: String,
+    pub from: usize,
+    pub to: Vec<usize>,
+    /// Cycles between a level appearing on `from` and reaching `to`
+    #[serde(default)]
+    pub delay: u64,
+    #[serde(default)]
+    pub invert: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BoardDescription {
+    #[serde(default, rename = "net")]
+    nets: Vec<Net>,
+}
+
+/// Board wiring between GPIOs. Each net drives its pins as `GPIODriver::Net`, so a chip driver
+/// on the same pin contends with it like it would on a real board.
+///
+/// A board description is a TOML file with one `[[net]]` table per net:
+///
+/// ```toml
+/// [[net]]
+/// name = "uart_loopback"
+/// from = 4
+/// to = [5]
+/// delay = 2
+/// invert = false
+/// ```
+#[derive(Debug, Default)]
+pub struct Netlist {
+    nets: Vec<Net>,
+    pending: Vec<VecDeque<u8>>,
+}
+
+impl Netlist {
+    pub fn new() -> Self {
+        Netlist::default()
+    }
+
+    pub fn from_toml(description: &str) -> Result<Self, std::string::String> {
+        let board: BoardDescription = toml::from_str(description)
+            .map_err(|err| format!("Failed to parse board description : {}", err))?;
+
+        let mut netlist = Netlist::new();
+        for net in board.nets {
+            netlist.add_net(net)?;
+        }
+        Ok(netlist)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, std::string::String> {
+        let description = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {} : {}", path.display(), err))?;
+        Netlist::from_toml(&description)
+    }
+
+    pub fn to_toml(&self) -> Result<std::string::String, std::string::String> {
+        let board = BoardDescription {
+            nets: self.nets.clone(),
+        };
+        toml::to_string(&board)
+            .map_err(|err| format!("Failed to serialize board description : {}", err))
+    }
+
+    pub fn add_net(&mut self, net: Net) -> Result<(), std::string::String> {
+        if self.get(&net.name).is_some() {
+            return Err(format!("Duplicate net : {}", net.name));
+        }
+        for pin in net.to.iter() {
+            if let Some(other) = self.nets.iter().find(|other| other.to.contains(pin)) {
+                return Err(format!(
+                    "GPIO{} is driven by both {} and {}",
+                    pin, other.name, net.name
+                ));
+            }
+        }
+
+        self.nets.push(net);
+        self.pending.push(VecDeque::new());
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Net> {
+        self.nets.iter().find(|net| net.name == name)
+    }
+
+    pub fn nets(&self) -> &[Net] {
+        &self.nets
+    }
+
+    /// Checks every pin a net touches exists on `pin_count` pins
+    pub fn validate(&self, pin_count: usize) -> Result<(), std::string::String> {
+        for net in self.nets.iter() {
+            if let Some(pin) = std::iter::once(&net.from)
+                .chain(net.to.iter())
+                .find(|pin| **pin >= pin_count)
+            {
+                return Err(format!("Net {} uses missing GPIO{}", net.name, pin));
+            }
+        }
+        Ok(())
+    }
+
+    /// Samples every net source and drives the levels that have made it through their delay onto
+    /// the destination pins. Called by `GPIO::tick`, so a net without delay is seen by the next
+    /// step.
+    pub fn propagate(&mut self, gpio: &mut GPIO) {
+        let levels = gpio.get_level_vector();
+        for (idx, (net, pending)) in self.nets.iter().zip(self.pending.iter_mut()).enumerate() {
+            pending.push_back(((levels >> net.from) & 1) as u8 ^ net.invert as u8);
+            if pending.len() as u64 <= net.delay {
+                continue;
+            }
+
+            let level = pending.pop_front().unwrap_or_default() as u64;
+            let mask = net.to.iter().fold(0, |mask, pin| mask | (1 << pin));
+            let driver = GPIODriver::Net(idx as u32);
+            gpio.set_driver_value(driver, if level == 1 { mask } else { 0 }, mask);
+            gpio.set_driver_enable(driver, mask, mask);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SM0: GPIODriver = GPIODriver::StateMachine { pio: 0, sm: 0 };
+
+    const LOOPBACK: &str = r#"
+[[net]]
+name = "uart_loopback"
+from = 4
+to = [5]
+delay = 2
+
+[[net]]
+name = "clk_n"
+from = 6
+to = [7, 8]
+invert = true
+"#;
+
+    #[test]
+    fn loopback_propagates_and_contends_with_chip_drivers() {
+        let netlist = Netlist::from_toml(LOOPBACK).unwrap();
+        assert_eq!(netlist.get("clk_n").unwrap().to, vec![7, 8]);
+        assert_eq!(
+            Netlist::from_toml(&netlist.to_toml().unwrap())
+                .unwrap()
+                .nets(),
+            netlist.nets()
+        );
+
+        let mut gpio = GPIO::new();
+        gpio.set_netlist(netlist).unwrap();
+        gpio.set_driver_value(SM0, 1 << 4, 1 << 4);
+        gpio.set_driver_enable(SM0, 1 << 4, 1 << 4);
+
+        gpio.tick();
+        assert_eq!(gpio.get_idx(7), 1);
+        assert_eq!(gpio.get_idx(8), 1);
+        gpio.tick();
+        assert_eq!(gpio.get_idx(5), 0);
+        gpio.tick();
+        assert_eq!(gpio.get_idx(5), 1);
+
+        // The state machine also drives the far end of the loopback, against the net
+        gpio.set_driver_enable(SM0, 1 << 5, 1 << 5);
+        assert_eq!(gpio.get_contention_vector(), 1 << 5);
+        let contentions = gpio.take_contentions();
+        assert_eq!(contentions.len(), 1);
+        assert_eq!(contentions[0].drivers, vec![SM0, GPIODriver::Net(0)]);
+
+        gpio.set_netlist(Netlist::new()).unwrap();
+        assert_eq!(gpio.get_contention_vector(), 0);
+        assert_eq!(gpio.get_idx(5), 0);
+    }
+
+    #[test]
+    fn nets_must_not_share_destinations_or_use_missing_pins() {
+        let mut netlist = Netlist::from_toml(LOOPBACK).unwrap();
+        let net = Net {
+            name: "other".to_string(),
+            from: 0,
+            to: vec![5],
+            delay: 0,
+            invert: false,
+        };
+        assert!(netlist.add_net(net.clone()).is_err());
+        assert!(netlist
+            .add_net(Net {
+                name: "clk_n".to_string(),
+                to: vec![9],
+                ..net.clone()
+            })
+            .is_err());
+
+        netlist
+            .add_net(Net {
+                to: vec![40],
+                ..net
+            })
+            .unwrap();
+        assert!(GPIO::new().set_netlist(netlist).is_err());
+    }
+}
@@ -0,0 +1,577 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::assembler::Assembly;
+use crate::instructions::*;
+
+/// The `-o` formats of pioasm, plus a Rust module of constants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    CSdk,
+    Python,
+    Hex,
+    Rust,
+}
+
+impl FromStr for OutputFormat {
+    type Err = std::string::String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "c-sdk" => Ok(Self::CSdk),
+            "python" => Ok(Self::Python),
+            "hex" => Ok(Self::Hex),
+            "rust" => Ok(Self::Rust),
+            _ => Err(format!("Unknown output format : {}", name)),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Name used by `.lang_opt` and `% lang {` blocks aimed at this format
+    pub fn lang(&self) -> &'static str {
+        match self {
+            Self::CSdk => "c-sdk",
+            Self::Python => "python",
+            Self::Hex => "hex",
+            Self::Rust => "rust",
+        }
+    }
+}
+
+/// Renders `assembly` the way pioasm does for the same source
+pub fn render(assembly: &Assembly, format: OutputFormat) -> Result<String, std::string::String> {
+    match format {
+        OutputFormat::CSdk => Ok(c_sdk(assembly)),
+        OutputFormat::Python => Ok(python(assembly)),
+        OutputFormat::Hex => hex(assembly),
+        OutputFormat::Rust => Ok(rust(assembly)),
+    }
+}
+
+/// Three line banner, `comment` being the line comment marker of the language
+fn header(out: &mut String, comment: &str, message: &str) {
+    let dashes = "-".repeat(message.len());
+    out.push_str(&format!("{} {} {}\n", comment, dashes, comment));
+    out.push_str(&format!("{} {} {}\n", comment, message, comment));
+    out.push_str(&format!("{} {} {}\n", comment, dashes, comment));
+    out.push('\n');
+}
+
+const BANNER: &str = "This file is autogenerated by pioasm; do not edit!";
+
+/// Public defines followed by public labels, each group ended by a blank line
+fn symbols(out: &mut String, symbols: &[Symbol], line: &dyn Fn(&Symbol) -> String) {
+    for is_label in [false, true].iter() {
+        let mut group = symbols
+            .iter()
+            .filter(|symbol| symbol.public && symbol.is_label == *is_label)
+            .peekable();
+        if group.peek().is_none() {
+            continue;
+        }
+        for symbol in group {
+            out.push_str(&line(symbol));
+        }
+        out.push('\n');
+    }
+}
+
+/// `% lang { ... %}` blocks of `program` concatenated per language, in name order like pioasm
+/// keeps them
+fn code_blocks(program: &Program) -> BTreeMap<&str, String> {
+    let mut blocks: BTreeMap<&str, String> = BTreeMap::new();
+    for (lang, text) in program.code_blocks.iter() {
+        blocks.entry(lang.as_str()).or_default().push_str(text);
+    }
+    blocks
+}
+
+/// `.pio.h` header for the C SDK
+pub fn c_sdk(assembly: &Assembly) -> String {
+    let mut out = String::new();
+    header(&mut out, "//", BANNER);
+    out.push_str("#pragma once\n\n");
+    out.push_str("#if !PICO_NO_HARDWARE\n#include \"hardware/pio.h\"\n#endif\n\n");
+    symbols(&mut out, &assembly.symbols, &|symbol| c_define("", symbol));
+
+    for program in assembly.programs.iter() {
+        header(&mut out, "//", &program.name);
+        let prefix = format!("{}_", program.name);
+        out.push_str(&format!(
+            "#define {}wrap_target {}\n",
+            prefix, program.wrap_target
+        ));
+        out.push_str(&format!("#define {}wrap {}\n\n", prefix, program.wrap));
+        symbols(&mut out, &program.symbols, &|symbol| {
+            c_define(&prefix, symbol)
+        });
+
+        out.push_str(&format!(
+            "static const uint16_t {}program_instructions[] = {{\n",
+            prefix
+        ));
+        for (address, word) in program.instructions.iter().enumerate() {
+            if address == program.wrap_target as usize {
+                out.push_str("            //     .wrap_target\n");
+            }
+            out.push_str(&format!(
+                "    0x{:04x}, // {:2}: {}\n",
+                word,
+                address,
+                disassemble_word(*word, &program.side_set)
+            ));
+            if address == program.wrap as usize {
+                out.push_str("            //     .wrap\n");
+            }
+        }
+        out.push_str("};\n\n");
+
+        out.push_str("#if !PICO_NO_HARDWARE\n");
+        out.push_str(&format!(
+            "static const struct pio_program {}program = {{\n",
+            prefix
+        ));
+        out.push_str(&format!(
+            "    .instructions = {}program_instructions,\n",
+            prefix
+        ));
+        out.push_str(&format!("    .length = {},\n", program.len()));
+        out.push_str(&format!(
+            "    .origin = {},\n",
+            program.origin.map_or(-1, |origin| origin as i32)
+        ));
+        out.push_str("};\n\n");
+        out.push_str(&format!(
+            "static inline pio_sm_config {}program_get_default_config(uint offset) {{\n",
+            prefix
+        ));
+        out.push_str("    pio_sm_config c = pio_get_default_sm_config();\n");
+        out.push_str(&format!(
+            "    sm_config_set_wrap(&c, offset + {}wrap_target, offset + {}wrap);\n",
+            prefix, prefix
+        ));
+        if program.side_set.bits() != 0 {
+            out.push_str(&format!(
+                "    sm_config_set_sideset(&c, {}, {}, {});\n",
+                program.side_set.bits(),
+                program.side_set.optional,
+                program.side_set.pindirs
+            ));
+        }
+        out.push_str("    return c;\n}\n");
+
+        // pioasm leaves a blank line for every language that has code blocks, whichever it is
+        for (lang, text) in code_blocks(program) {
+            out.push('\n');
+            if lang == OutputFormat::CSdk.lang() {
+                out.push_str(&text);
+            }
+        }
+        out.push_str("#endif\n\n");
+    }
+    out
+}
+
+fn c_define(prefix: &str, symbol: &Symbol) -> String {
+    if symbol.is_label {
+        format!(
+            "#define {}offset_{} {}u\n",
+            prefix, symbol.name, symbol.value
+        )
+    } else {
+        format!("#define {}{} {}\n", prefix, symbol.name, symbol.value)
+    }
+}
+
+fn python_assignment(prefix: &str, symbol: &Symbol) -> String {
+    let offset = if symbol.is_label { "offset_" } else { "" };
+    format!("{}{}{} = {}\n", prefix, offset, symbol.name, symbol.value)
+}
+
+/// MicroPython module using `rp2.asm_pio`, with `.lang_opt python` settings passed to the
+/// decorator
+pub fn python(assembly: &Assembly) -> String {
+    let mut out = String::new();
+    header(&mut out, "#", BANNER);
+    out.push_str("import rp2\nfrom machine import Pin\n");
+    symbols(&mut out, &assembly.symbols, &|symbol| {
+        python_assignment("", symbol)
+    });
+
+    for program in assembly.programs.iter() {
+        header(&mut out, "#", &program.name);
+        let prefix = format!("{}_", program.name);
+        symbols(&mut out, &program.symbols, &|symbol| {
+            python_assignment(&prefix, symbol)
+        });
+
+        let options: Vec<String> = program
+            .lang_opts
+            .iter()
+            .filter(|opt| opt.lang == OutputFormat::Python.lang())
+            .map(|opt| format!("{}={}", opt.name, opt.value))
+            .collect();
+        out.push_str(&format!("@rp2.asm_pio({})\n", options.join(", ")));
+        out.push_str(&format!("def {}():\n", program.name));
+
+        // Every jump target gets a label named after its address
+        let targets: Vec<u8> = program
+            .instructions
+            .iter()
+            .filter(|word| *word >> 13 == 0)
+            .map(|word| (word & 0x1f) as u8)
+            .collect();
+        for (address, word) in program.instructions.iter().enumerate() {
+            if address == program.wrap_target as usize {
+                out.push_str("    wrap_target()\n");
+            }
+            if targets.contains(&(address as u8)) {
+                out.push_str(&format!("    label(\"{}\")\n", address));
+            }
+            out.push_str(&format!(
+                "    {} # {}\n",
+                python_instruction(*word, &program.side_set),
+                address
+            ));
+            if address == program.wrap as usize {
+                out.push_str("    wrap()\n");
+            }
+        }
+        out.push('\n');
+
+        if let Some(text) = code_blocks(program).get(OutputFormat::Python.lang()) {
+            out.push_str(text);
+        }
+    }
+    out
+}
+
+/// One instruction in the `rp2.asm_pio` dialect, e.g. `jmp(not_x, "3")         .side(1) [1]`
+fn python_instruction(word: u16, side_set: &SideSet) -> String {
+    let operation = if is_reserved_word(word) {
+        format!("word({})", word)
+    } else {
+        match PIOInstruction::decode(word as u32) {
+            Ok(instruction) => python_operation(&instruction),
+            Err(_) => format!("word({})", word),
+        }
+    };
+
+    let mut text = format!("{:<24}", operation);
+    let field = ((word >> 8) & 0x1f) as u8;
+    let bits = side_set.bits();
+    if bits != 0 && (!side_set.optional || field & 0x10 != 0) {
+        let value_mask = if side_set.optional { 0xf } else { 0x1f };
+        text.push_str(&format!(".side({})", (field & value_mask) >> (5 - bits)));
+    }
+    let delay = field & side_set.max_delay();
+    if delay != 0 {
+        text.push_str(&format!(" [{}]", delay));
+    }
+    text
+}
+
+fn python_operation(instruction: &PIOInstruction) -> String {
+    let irq = |index: u8| {
+        if index & 0x10 != 0 {
+            format!("rel({})", index & 7)
+        } else {
+            (index & 7).to_string()
+        }
+    };
+    let count = |bit_count: u8| if bit_count == 0 { 32 } else { bit_count };
+
+    match instruction {
+        PIOInstruction::JMP {
+            condition, address, ..
+        } => {
+            let condition = match condition {
+                JmpCondition::Always => "",
+                JmpCondition::ScratchXZero => "not_x, ",
+                JmpCondition::ScratchXNonZeroPostDecrement => "x_dec, ",
+                JmpCondition::ScratchYZero => "not_y, ",
+                JmpCondition::ScratchYNonZeroPostDecrement => "y_dec, ",
+                JmpCondition::ScratchXNotEqualScratchY => "x_not_y, ",
+                JmpCondition::BranchOnInputPin => "pin, ",
+                JmpCondition::OutputShiftRegisterNotEmpty => "not_osre, ",
+            };
+            format!("jmp({}\"{}\")", condition, address)
+        }
+        PIOInstruction::WAIT {
+            polarity,
+            source,
+            index,
+            ..
+        } => {
+            let source = match source {
+                WaitSource::GPIO => format!("gpio, {}", index),
+                WaitSource::Pin => format!("pin, {}", index),
+                _ => format!("irq, {}", irq(*index)),
+            };
+            format!("wait({}, {})", *polarity as u8, source)
+        }
+        PIOInstruction::IN {
+            source, bit_count, ..
+        } => {
+            let source = match source {
+                InSource::PINS => "pins",
+                InSource::X => "x",
+                InSource::Y => "y",
+                InSource::NULL => "null",
+                InSource::ISR => "isr",
+                _ => "osr",
+            };
+            format!("in_({}, {})", source, count(*bit_count))
+        }
+        PIOInstruction::OUT {
+            destination,
+            bit_count,
+            ..
+        } => {
+            let destination = match destination {
+                OutDestination::PINS => "pins",
+                OutDestination::X => "x",
+                OutDestination::Y => "y",
+                OutDestination::NULL => "null",
+                OutDestination::PINDIRS => "pindirs",
+                OutDestination::PC => "pc",
+                OutDestination::ISR => "isr",
+                OutDestination::EXEC => "exec",
+            };
+            format!("out({}, {})", destination, count(*bit_count))
+        }
+        PIOInstruction::PUSH { if_full, block, .. } => format!(
+            "push({}{})",
+            if *if_full { "iffull, " } else { "" },
+            if *block { "block" } else { "noblock" }
+        ),
+        PIOInstruction::PULL {
+            if_empty, block, ..
+        } => format!(
+            "pull({}{})",
+            if *if_empty { "ifempty, " } else { "" },
+            if *block { "block" } else { "noblock" }
+        ),
+        PIOInstruction::MOV {
+            destination,
+            op,
+            source,
+            ..
+        } => {
+            let destination = match destination {
+                MovDestination::PINS => "pins",
+                MovDestination::X => "x",
+                MovDestination::Y => "y",
+                MovDestination::EXEC => "exec",
+                MovDestination::PC => "pc",
+                MovDestination::ISR => "isr",
+                _ => "osr",
+            };
+            let source = match source {
+                MovSource::PINS => "pins",
+                MovSource::X => "x",
+                MovSource::Y => "y",
+                MovSource::NULL => "null",
+                MovSource::STATUS => "status",
+                MovSource::ISR => "isr",
+                _ => "osr",
+            };
+            if destination == source && (destination == "x" || destination == "y") {
+                if let MovOp::None = op {
+                    return "nop()".to_string();
+                }
+            }
+            match op {
+                MovOp::Invert => format!("mov({}, invert({}))", destination, source),
+                MovOp::BitReverse => format!("mov({}, reverse({}))", destination, source),
+                _ => format!("mov({}, {})", destination, source),
+            }
+        }
+        PIOInstruction::IRQ {
+            clear, wait, index, ..
+        } => {
+            let mode = if *clear {
+                "clear, "
+            } else if *wait {
+                "block, "
+            } else {
+                ""
+            };
+            format!("irq({}{})", mode, irq(*index))
+        }
+        PIOInstruction::SET {
+            destination, data, ..
+        } => {
+            let destination = match destination {
+                SetDestination::PINS => "pins",
+                SetDestination::X => "x",
+                SetDestination::Y => "y",
+                _ => "pindirs",
+            };
+            format!("set({}, {})", destination, data)
+        }
+    }
+}
+
+/// One instruction word per line in hex. Like pioasm, only a single program can be written.
+pub fn hex(assembly: &Assembly) -> Result<String, std::string::String> {
+    if assembly.programs.len() != 1 {
+        return Err("hex output only supports a single program input".to_string());
+    }
+    Ok(assembly.programs[0]
+        .instructions
+        .iter()
+        .map(|word| format!("{:04x}\n", word))
+        .collect())
+}
+
+/// A module of constants per program, for loading programs into the emulator or a HAL without
+/// assembling at run time
+pub fn rust(assembly: &Assembly) -> String {
+    let mut out = String::new();
+    header(&mut out, "//", BANNER);
+    symbols(&mut out, &assembly.symbols, &rust_const);
+
+    for (index, program) in assembly.programs.iter().enumerate() {
+        if index != 0 {
+            out.push('\n');
+        }
+        out.push_str(&format!("pub mod {} {{\n", program.name));
+        out.push_str(&format!(
+            "    pub const WRAP_TARGET: u8 = {};\n",
+            program.wrap_target
+        ));
+        out.push_str(&format!("    pub const WRAP: u8 = {};\n", program.wrap));
+        let origin = match program.origin {
+            Some(origin) => format!("Some({})", origin),
+            None => "None".to_string(),
+        };
+        out.push_str(&format!("    pub const ORIGIN: Option<u8> = {};\n", origin));
+        out.push_str(&format!(
+            "    /// Side-set bits including the enable bit of an optional side-set\n    pub const SIDE_SET_BITS: u8 = {};\n",
+            program.side_set.bits()
+        ));
+        out.push_str(&format!(
+            "    pub const SIDE_SET_OPTIONAL: bool = {};\n",
+            program.side_set.optional
+        ));
+        out.push_str(&format!(
+            "    pub const SIDE_SET_PINDIRS: bool = {};\n\n",
+            program.side_set.pindirs
+        ));
+
+        symbols(&mut out, &program.symbols, &|symbol| {
+            format!("    {}", rust_const(symbol))
+        });
+
+        out.push_str(&format!(
+            "    pub const INSTRUCTIONS: [u16; {}] = [\n",
+            program.len()
+        ));
+        for (address, word) in program.instructions.iter().enumerate() {
+            if address == program.wrap_target as usize {
+                out.push_str("        //     .wrap_target\n");
+            }
+            out.push_str(&format!(
+                "        0x{:04x}, // {:2}: {}\n",
+                word,
+                address,
+                disassemble_word(*word, &program.side_set).trim_end()
+            ));
+            if address == program.wrap as usize {
+                out.push_str("        //     .wrap\n");
+            }
+        }
+        out.push_str("    ];\n");
+
+        if let Some(text) = code_blocks(program).get(OutputFormat::Rust.lang()) {
+            out.push('\n');
+            out.push_str(text);
+        }
+        out.push_str("}\n");
+    }
+    out
+}
+
+fn rust_const(symbol: &Symbol) -> String {
+    let name = symbol.name.to_ascii_uppercase();
+    if symbol.is_label {
+        format!("pub const OFFSET_{}: u8 = {};\n", name, symbol.value)
+    } else {
+        format!("pub const {}: i32 = {};\n", name, symbol.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    // Expected files are pioasm's output for the sources next to them
+    const WS2812: &str = include_str!("../testdata/ws2812.pio");
+    const SQUAREWAVE: &str = include_str!("../testdata/squarewave.pio");
+
+    fn render_source(source: &str, format: OutputFormat) -> String {
+        render(&assemble(source).unwrap(), format).unwrap()
+    }
+
+    #[test]
+    fn c_sdk_matches_pioasm() {
+        assert_eq!(
+            render_source(WS2812, OutputFormat::CSdk),
+            include_str!("../testdata/ws2812.pio.h")
+        );
+        assert_eq!(
+            render_source(SQUAREWAVE, OutputFormat::CSdk),
+            include_str!("../testdata/squarewave.pio.h")
+        );
+    }
+
+    #[test]
+    fn python_matches_pioasm() {
+        assert_eq!(
+            render_source(WS2812, OutputFormat::Python),
+            include_str!("../testdata/ws2812.py")
+        );
+    }
+
+    #[test]
+    fn hex_matches_pioasm_and_takes_a_single_program() {
+        assert_eq!(
+            render_source(SQUAREWAVE, OutputFormat::Hex),
+            include_str!("../testdata/squarewave.hex")
+        );
+        assert_eq!(
+            render(&assemble(WS2812).unwrap(), OutputFormat::Hex).unwrap_err(),
+            "hex output only supports a single program input"
+        );
+    }
+
+    #[test]
+    fn code_blocks_are_grouped_by_language() {
+        let assembly = assemble(
+            ".program blocks
+    nop
+% c-sdk {
+// first
+%}
+% python {
+# python
+%}
+% c-sdk {
+// second
+%}",
+        )
+        .unwrap();
+
+        let c_sdk = c_sdk(&assembly);
+        assert!(
+            c_sdk.ends_with("    return c;\n}\n\n// first\n// second\n\n#endif\n\n"),
+            "{}",
+            c_sdk
+        );
+        assert!(python(&assembly).ends_with("    wrap()\n\n# python\n"));
+        assert!(rust(&assembly).ends_with("    ];\n}\n"));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::gpio::MAX_PIN_COUNT;
+
+/// A name given to one pin ("CLK") or to a run of consecutive pins ("DATA[7:0]")
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PinGroup {
+    name: String,
+    /// Bit number of the lowest pin, `None` for a plain pin name
+    lsb: Option<u32>,
+    /// Physical pins, least significant bit first
+    pins: Vec<usize>,
+}
+
+impl PinGroup {
+    fn bit_name(&self, bit: usize) -> String {
+        match self.lsb {
+            Some(lsb) => format!("{}[{}]", self.name, lsb as usize + bit),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Splits "DATA[7:0]" into ("DATA", 7, 0) and "DATA[3]" into ("DATA", 3, 3)
+fn parse_spec(spec: &str) -> Result<(&str, Option<(u32, u32)>), std::string::String> {
+    let spec = spec.trim();
+    let (name, range) = match spec.find('[') {
+        Some(open) if spec.ends_with(']') => (&spec[..open], Some(&spec[open + 1..spec.len() - 1])),
+        Some(_) => return Err(format!("Invalid pin name : {}", spec)),
+        None => (spec, None),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("Invalid pin name : {}", spec));
+    }
+
+    let range = match range {
+        Some(range) => {
+            let mut bounds = range
+                .splitn(2, ':')
+                .map(|bound| bound.trim().parse::<u32>());
+            let msb = bounds.next();
+            let lsb = bounds.next();
+            match (msb, lsb) {
+                (Some(Ok(msb)), Some(Ok(lsb))) if msb >= lsb => Some((msb, lsb)),
+                (Some(Ok(bit)), None) => Some((bit, bit)),
+                _ => return Err(format!("Invalid pin name : {}", spec)),
+            }
+        }
+        None => None,
+    };
+    Ok((name, range))
+}
+
+/// Number of pins `spec` names, checked against the largest GPIO bank so that nothing is built
+/// for an absurd range such as "D[4294967295:0]"
+pub fn spec_width(spec: &str) -> Result<usize, std::string::String> {
+    let (_, range) = parse_spec(spec)?;
+    let width = match range {
+        Some((msb, lsb)) => msb.checked_sub(lsb).and_then(|width| width.checked_add(1)),
+        None => Some(1),
+    };
+    width
+        .map(|width| width as usize)
+        .filter(|width| *width <= MAX_PIN_COUNT)
+        .ok_or_else(|| format!("{} is wider than {} pins", spec, MAX_PIN_COUNT))
+}
+
+/// Names attached to pins, used to look pins up and to label them in dumps and errors
+#[derive(Debug, Clone, Default)]
+pub struct PinNames {
+    groups: Vec<PinGroup>,
+}
+
+impl PinNames {
+    pub fn new() -> Self {
+        PinNames::default()
+    }
+
+    /// Names the pins starting at `base`. `spec` is either a single pin name such as "CLK" or a
+    /// bus such as "DATA[7:0]", whose lowest bit lands on `base`.
+    pub fn add(&mut self, spec: &str, base: usize) -> Result<(), std::string::String> {
+        let (name, range) = parse_spec(spec)?;
+        if self.groups.iter().any(|group| group.name == name) {
+            return Err(format!("Duplicate pin name : {}", name));
+        }
+
+        let width = spec_width(spec)?;
+        let end = base
+            .checked_add(width)
+            .filter(|end| *end <= MAX_PIN_COUNT)
+            .ok_or_else(|| format!("{} at GPIO{} runs past the last GPIO", spec, base))?;
+        let pins: Vec<usize> = (base..end).collect();
+        for pin in pins.iter() {
+            if let Some(other) = self.name(*pin) {
+                return Err(format!("GPIO{} is already named {}", pin, other));
+            }
+        }
+
+        self.groups.push(PinGroup {
+            name: name.to_string(),
+            lsb: range.map(|(_, lsb)| lsb),
+            pins,
+        });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.groups.retain(|group| group.name != name);
+    }
+
+    /// The pins `spec` refers to, least significant bit first. Besides the names given to `add`,
+    /// single bits ("DATA[3]") and slices ("DATA[3:0]") of a bus can be looked up.
+    pub fn lookup(&self, spec: &str) -> Result<Vec<usize>, std::string::String> {
+        let (name, range) = parse_spec(spec)?;
+        let group = self
+            .groups
+            .iter()
+            .find(|group| group.name == name)
+            .ok_or(format!("Unknown pin name : {}", spec))?;
+
+        match (range, group.lsb) {
+            (None, _) => Ok(group.pins.clone()),
+            (Some((msb, lsb)), Some(group_lsb))
+                if lsb >= group_lsb && ((msb - group_lsb) as usize) < group.pins.len() =>
+            {
+                Ok(group.pins[(lsb - group_lsb) as usize..=(msb - group_lsb) as usize].to_vec())
+            }
+            _ => Err(format!("{} is out of range for {}", spec, group.name)),
+        }
+    }
+
+    /// The name of a single pin, `None` if it has not been named
+    pub fn name(&self, pin: usize) -> Option<String> {
+        self.groups.iter().find_map(|group| {
+            group
+                .pins
+                .iter()
+                .position(|other| *other == pin)
+                .map(|bit| group.bit_name(bit))
+        })
+    }
+
+    /// The name of a single pin, falling back to its GPIO number
+    pub fn label(&self, pin: usize) -> String {
+        self.name(pin).unwrap_or_else(|| format!("GPIO{}", pin))
+    }
+
+    /// Every named group as its full name, e.g. "DATA[7:0]", along with its pins
+    pub fn groups(&self) -> impl Iterator<Item = (String, &[usize])> {
+        self.groups.iter().map(|group| {
+            let name = match group.lsb {
+                Some(lsb) => format!(
+                    "{}[{}:{}]",
+                    group.name,
+                    lsb as usize + group.pins.len() - 1,
+                    lsb
+                ),
+                None => group.name.clone(),
+            };
+            (name, group.pins.as_slice())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buses_are_looked_up_by_name_bit_and_slice() {
+        let mut names = PinNames::new();
+        names.add("CLK", 2).unwrap();
+        names.add("DATA[7:0]", 8).unwrap();
+
+        assert_eq!(names.lookup("CLK").unwrap(), vec![2]);
+        assert_eq!(names.lookup("DATA").unwrap(), (8..16).collect::<Vec<_>>());
+        assert_eq!(names.lookup("DATA[3]").unwrap(), vec![11]);
+        assert_eq!(names.lookup("DATA[7:6]").unwrap(), vec![14, 15]);
+        assert_eq!(names.name(9).as_deref(), Some("DATA[1]"));
+        assert_eq!(names.label(3), "GPIO3");
+
+        let groups: Vec<String> = names.groups().map(|(name, _)| name).collect();
+        assert_eq!(groups, vec!["CLK", "DATA[7:0]"]);
+
+        names.remove("CLK");
+        assert!(names.lookup("CLK").is_err());
+    }
+
+    #[test]
+    fn invalid_duplicate_and_out_of_range_names_are_rejected() {
+        let mut names = PinNames::new();
+        names.add("ADDR[4:1]", 0).unwrap();
+
+        assert!(names.add("ADDR", 10).is_err());
+        assert!(names.add("CS", 2).is_err());
+        assert!(names.add("BAD NAME", 10).is_err());
+        assert!(names.add("BUS[0:3]", 10).is_err());
+        assert!(names.add("BUS[3", 10).is_err());
+        assert!(names.add("BUS[4294967295:0]", 0).is_err());
+        assert!(names.add("BUS[64:1]", 0).is_err());
+        assert!(names.add("BUS[1:0]", 63).is_err());
+        assert!(names.add("BUS[1:0]", usize::MAX).is_err());
+        assert_eq!(spec_width("BUS[63:0]"), Ok(64));
+
+        assert!(names.lookup("ADDR[0]").is_err());
+        assert!(names.lookup("ADDR[5:1]").is_err());
+        assert!(names.lookup("MISSING").is_err());
+        assert_eq!(names.lookup("ADDR[2:1]").unwrap(), vec![0, 1]);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::memory_backing::*;
+
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct RegisterInfo {
+    pub name: String,
+    pub offset: usize,
+    /// The fields `register_access` enforces, empty for registers that hold a single value
+    pub fields: Vec<FieldAccess>,
+}
+
+/// Lists every register of the PIO block in address order along with its decoded fields
+pub fn register_map() -> Vec<RegisterInfo> {
+    let mut names = vec![
+        "CTRL".to_string(),
+        "FSTAT".to_string(),
+        "FDEBUG".to_string(),
+        "FLEVEL".to_string(),
+    ];
+    names.extend((0..SM_COUNT).map(|sm_id| format!("TXF{}", sm_id)));
+    names.extend((0..SM_COUNT).map(|sm_id| format!("RXF{}", sm_id)));
+    names.extend(
+        [
+            "IRQ",
+            "IRQ_FORCE",
+            "INPUT_SYNC_BYPASS",
+            "DBG_PADOUT",
+            "DBG_PADOE",
+            "DBG_CFGINFO",
+        ]
+        .iter()
+        .map(|name| name.to_string()),
+    );
+    names.extend((0..IMEM_SIZE).map(|idx| format!("INSTR_MEM{}", idx)));
+    for sm_id in 0..SM_COUNT {
+        names.extend(
+            [
+                "CLKDIV",
+                "EXECCTRL",
+                "SHIFTCTRL",
+                "ADDR",
+                "INSTR",
+                "PINCTRL",
+            ]
+            .iter()
+            .map(|register| format!("SM{}_{}", sm_id, register)),
+        );
+    }
+    names.extend(
+        [
+            "INTR",
+            "IRQ0_INTE",
+            "IRQ0_INTF",
+            "IRQ0_INTS",
+            "IRQ1_INTE",
+            "IRQ1_INTF",
+            "IRQ1_INTS",
+        ]
+        .iter()
+        .map(|name| name.to_string()),
+    );
+
+    names
+        .into_iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            let offset = idx * 4;
+            let fields = register_access(offset)
+                .map(|fields| {
+                    fields
+                        .iter()
+                        .filter(|field| field.name.is_some())
+                        .copied()
+                        .collect()
+                })
+                .unwrap_or_default();
+            RegisterInfo {
+                name,
+                offset,
+                fields,
+            }
+        })
+        .collect()
+}
+
+/// Raw copy of every register in the block, indexed by `offset / 4`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterSnapshot {
+    pub values: Vec<u32>,
+}
+
+impl RegisterSnapshot {
+    pub fn capture(backing: &PIOMemoryBacking) -> Result<Self, std::string::String> {
+        let values = (0..std::mem::size_of::<PIOMemoryBacking>())
+            .step_by(4)
+            .map(|offset| backing.read_offset(offset))
+            .collect::<Result<Vec<u32>, std::string::String>>()?;
+        Ok(RegisterSnapshot { values })
+    }
+
+    pub fn get(&self, offset: usize) -> u32 {
+        self.values[offset / 4]
+    }
+
+    /// Lists every field that differs between `self` and `other`. Registers without named fields
+    /// are compared as a whole, and so are registers whose change lies only in reserved or
+    /// unnamed bits.
+    pub fn diff(&self, other: &RegisterSnapshot) -> Vec<RegisterChange> {
+        let mut changes = Vec::new();
+        for register in register_map() {
+            let old = self.get(register.offset);
+            let new = other.get(register.offset);
+            if old == new {
+                continue;
+            }
+
+            let field_changes: Vec<RegisterChange> = register
+                .fields
+                .iter()
+                .filter(|field| field.read(old) != field.read(new))
+                .map(|field| RegisterChange {
+                    register: register.name.clone(),
+                    field: field.name,
+                    old: field.read(old),
+                    new: field.read(new),
+                })
+                .collect();
+            if field_changes.is_empty() {
+                changes.push(RegisterChange {
+                    register: register.name.clone(),
+                    field: None,
+                    old,
+                    new,
+                });
+            }
+            changes.extend(field_changes);
+        }
+        changes
+    }
+}
+
+impl fmt::Display for RegisterSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for register in register_map() {
+            let value = self.get(register.offset);
+            writeln!(
+                f,
+                "0x{:03X} {:<18} 0x{:08X}",
+                register.offset, register.name, value
+            )?;
+            for field in register.fields.iter() {
+                let bits = if field.msb() == field.shift {
+                    format!("[{}]", field.shift)
+                } else {
+                    format!("[{}:{}]", field.msb(), field.shift)
+                };
+                writeln!(
+                    f,
+                    "        {:<16} {:<7} 0x{:X}",
+                    field.name.unwrap_or_default(),
+                    bits,
+                    field.read(value)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub register: String,
+    pub field: Option<&'static str>,
+    pub old: u32,
+    pub new: u32,
+}
+
+impl fmt::Display for RegisterChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.field {
+            Some(field) => write!(
+                f,
+                "{}.{} : 0x{:X} -> 0x{:X}",
+                self.register, field, self.old, self.new
+            ),
+            None => write!(
+                f,
+                "{} : 0x{:08X} -> 0x{:08X}",
+                self.register, self.old, self.new
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memoffset::offset_of;
+    use tock_registers::interfaces::{ReadWriteable, Writeable};
+
+    #[test]
+    fn register_map_covers_every_register_with_its_access_fields() {
+        let map = register_map();
+        assert_eq!(map.len(), std::mem::size_of::<PIOMemoryBacking>() / 4);
+        for (idx, register) in map.iter().enumerate() {
+            assert_eq!(register.offset, idx * 4, "{}", register.name);
+        }
+
+        let fstat = &map[1];
+        assert_eq!(fstat.name, "FSTAT");
+        let names: Vec<_> = fstat.fields.iter().map(|field| field.name).collect();
+        assert_eq!(
+            names,
+            vec![
+                Some("TXEMPTY"),
+                Some("TXFULL"),
+                Some("RXEMPTY"),
+                Some("RXFULL")
+            ]
+        );
+        assert!(fstat
+            .fields
+            .iter()
+            .all(|field| field.access == RegisterAccess::RO));
+        assert!(map[4].fields.is_empty(), "{}", map[4].name);
+    }
+
+    #[test]
+    fn diff_reports_changed_fields() {
+        let backing = PIOMemoryBacking::default();
+        let before = RegisterSnapshot::capture(&backing).unwrap();
+        backing.SM1_EXECCTRL.modify(SM_EXECCTRL::WRAP_TOP.val(7));
+        backing.TXF2.set(0x1234);
+        let after = RegisterSnapshot::capture(&backing).unwrap();
+
+        let changes: Vec<String> = before
+            .diff(&after)
+            .iter()
+            .map(|change| change.to_string())
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                "TXF2 : 0x00000000 -> 0x00001234",
+                "SM1_EXECCTRL.WRAP_TOP : 0x1F -> 0x7",
+            ]
+        );
+        assert!(after
+            .to_string()
+            .contains("        WRAP_TOP         [16:12] 0x7\n"));
+    }
+
+    #[test]
+    fn diff_reports_changes_to_reserved_bits() {
+        let backing = PIOMemoryBacking::default();
+        let before = RegisterSnapshot::capture(&backing).unwrap();
+        // The low 16 bits of SHIFTCTRL are reserved, as after importing a foreign register image
+        let mut values = before.values.clone();
+        values[offset_of!(PIOMemoryBacking, SM0_SHIFTCTRL) / 4] |= 1;
+        let after = RegisterSnapshot { values };
+
+        let changes: Vec<String> = before
+            .diff(&after)
+            .iter()
+            .map(|change| change.to_string())
+            .collect();
+        assert_eq!(changes, vec!["SM0_SHIFTCTRL : 0x000C0000 -> 0x000C0001"]);
+    }
+}
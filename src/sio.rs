@@ -0,0 +1,180 @@
+use crate::gpio::{lock, GPIODriver, SharedGPIO, GPIO};
+
+// SIO register offsets. The GPIO_HI registers cover pins 32 and up, like the RP2350 where they
+// reach GPIO47.
+pub const GPIO_IN: usize = 0x004;
+pub const GPIO_HI_IN: usize = 0x008;
+pub const GPIO_OUT: usize = 0x010;
+pub const GPIO_OUT_SET: usize = 0x014;
+pub const GPIO_OUT_CLR: usize = 0x018;
+pub const GPIO_OUT_XOR: usize = 0x01C;
+pub const GPIO_OE: usize = 0x020;
+pub const GPIO_OE_SET: usize = 0x024;
+pub const GPIO_OE_CLR: usize = 0x028;
+pub const GPIO_OE_XOR: usize = 0x02C;
+pub const GPIO_HI_OUT: usize = 0x030;
+pub const GPIO_HI_OUT_SET: usize = 0x034;
+pub const GPIO_HI_OUT_CLR: usize = 0x038;
+pub const GPIO_HI_OUT_XOR: usize = 0x03C;
+pub const GPIO_HI_OE: usize = 0x040;
+pub const GPIO_HI_OE_SET: usize = 0x044;
+pub const GPIO_HI_OE_CLR: usize = 0x048;
+pub const GPIO_HI_OE_XOR: usize = 0x04C;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Out,
+    Enable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Write,
+    Set,
+    Clear,
+    Xor,
+}
+
+/// The CPU side GPIO registers of the single-cycle IO block. Pins only follow GPIO_OUT and
+/// GPIO_OE while their FUNCSEL selects SIO.
+#[derive(Debug)]
+pub struct SIO {
+    gpio: SharedGPIO,
+}
+
+impl SIO {
+    pub fn new(gpio: SharedGPIO) -> Self {
+        SIO { gpio }
+    }
+
+    pub fn read_register(&self, offset: usize) -> Result<u32, std::string::String> {
+        let borrowed_gpio: &GPIO = &lock(&self.gpio);
+        let state = borrowed_gpio.get_driver(GPIODriver::CPU);
+        let value = match offset {
+            GPIO_IN => borrowed_gpio.get_peripheral_input_vector(),
+            GPIO_HI_IN => borrowed_gpio.get_peripheral_input_vector() >> 32,
+            GPIO_OUT => state.value,
+            GPIO_HI_OUT => state.value >> 32,
+            GPIO_OE => state.enable,
+            GPIO_HI_OE => state.enable >> 32,
+            // The SET, CLR and XOR aliases are write only
+            GPIO_OUT_SET | GPIO_OUT_CLR | GPIO_OUT_XOR | GPIO_OE_SET | GPIO_OE_CLR
+            | GPIO_OE_XOR | GPIO_HI_OUT_SET | GPIO_HI_OUT_CLR | GPIO_HI_OUT_XOR
+            | GPIO_HI_OE_SET | GPIO_HI_OE_CLR | GPIO_HI_OE_XOR => 0,
+            _ => return Err(format!("Invalid SIO register offset : {:#x}", offset)),
+        };
+        Ok(value as u32)
+    }
+
+    pub fn write_register(&mut self, offset: usize, value: u32) -> Result<(), std::string::String> {
+        let (target, operation, shift) = match offset {
+            GPIO_OUT => (Target::Out, Operation::Write, 0),
+            GPIO_OUT_SET => (Target::Out, Operation::Set, 0),
+            GPIO_OUT_CLR => (Target::Out, Operation::Clear, 0),
+            GPIO_OUT_XOR => (Target::Out, Operation::Xor, 0),
+            GPIO_OE => (Target::Enable, Operation::Write, 0),
+            GPIO_OE_SET => (Target::Enable, Operation::Set, 0),
+            GPIO_OE_CLR => (Target::Enable, Operation::Clear, 0),
+            GPIO_OE_XOR => (Target::Enable, Operation::Xor, 0),
+            GPIO_HI_OUT => (Target::Out, Operation::Write, 32),
+            GPIO_HI_OUT_SET => (Target::Out, Operation::Set, 32),
+            GPIO_HI_OUT_CLR => (Target::Out, Operation::Clear, 32),
+            GPIO_HI_OUT_XOR => (Target::Out, Operation::Xor, 32),
+            GPIO_HI_OE => (Target::Enable, Operation::Write, 32),
+            GPIO_HI_OE_SET => (Target::Enable, Operation::Set, 32),
+            GPIO_HI_OE_CLR => (Target::Enable, Operation::Clear, 32),
+            GPIO_HI_OE_XOR => (Target::Enable, Operation::Xor, 32),
+            GPIO_IN | GPIO_HI_IN => return Ok(()),
+            _ => return Err(format!("Invalid SIO register offset : {:#x}", offset)),
+        };
+
+        let value = (value as u64) << shift;
+        let mask = 0xFFFF_FFFFu64 << shift;
+        let borrowed_gpio: &mut GPIO = &mut lock(&self.gpio);
+        let state = borrowed_gpio.get_driver(GPIODriver::CPU);
+        let current = match target {
+            Target::Out => state.value,
+            Target::Enable => state.enable,
+        };
+        let new = match operation {
+            Operation::Write => value,
+            Operation::Set => current | value,
+            Operation::Clear => current & !value,
+            Operation::Xor => current ^ value,
+        };
+
+        match target {
+            Target::Out => borrowed_gpio.set_driver_value(GPIODriver::CPU, new, mask),
+            Target::Enable => borrowed_gpio.set_driver_enable(GPIODriver::CPU, new, mask),
+        }
+        Ok(())
+    }
+
+    /// Levels of every pin as seen by the CPU, GPIO_HI_IN in the upper half
+    pub fn gpio_in(&self) -> u64 {
+        lock(&self.gpio).get_peripheral_input_vector()
+    }
+
+    pub fn gpio_out(&self) -> u64 {
+        lock(&self.gpio).get_driver(GPIODriver::CPU).value
+    }
+
+    pub fn gpio_oe(&self) -> u64 {
+        lock(&self.gpio).get_driver(GPIODriver::CPU).enable
+    }
+
+    pub fn set_out(&mut self, value: u64, mask: u64) {
+        lock(&self.gpio).set_driver_value(GPIODriver::CPU, value, mask);
+    }
+
+    pub fn set_oe(&mut self, enable: u64, mask: u64) {
+        lock(&self.gpio).set_driver_enable(GPIODriver::CPU, enable, mask);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::{shared, FunctionSelect};
+
+    #[test]
+    fn set_clr_and_xor_aliases_update_out_and_oe() {
+        let gpio = shared(GPIO::with_pin_count(48, FunctionSelect::SIO).unwrap());
+        let mut sio = SIO::new(gpio.clone());
+
+        sio.write_register(GPIO_OUT, 0b0110).unwrap();
+        sio.write_register(GPIO_OUT_SET, 0b1000).unwrap();
+        sio.write_register(GPIO_OUT_CLR, 0b0010).unwrap();
+        sio.write_register(GPIO_OUT_XOR, 0b0001).unwrap();
+        assert_eq!(sio.read_register(GPIO_OUT).unwrap(), 0b1101);
+
+        sio.write_register(GPIO_OE_SET, 0b0011).unwrap();
+        sio.write_register(GPIO_OE_XOR, 0b0110).unwrap();
+        assert_eq!(sio.read_register(GPIO_OE).unwrap(), 0b0101);
+
+        sio.write_register(GPIO_HI_OUT_SET, 0b11).unwrap();
+        sio.write_register(GPIO_HI_OUT_CLR, 0b01).unwrap();
+        assert_eq!(sio.read_register(GPIO_HI_OUT).unwrap(), 0b10);
+        assert_eq!(sio.gpio_out(), (0b10 << 32) | 0b1101);
+
+        // The aliases read as zero and GPIO_IN ignores writes
+        assert_eq!(sio.read_register(GPIO_OUT_SET).unwrap(), 0);
+        sio.write_register(GPIO_IN, !0).unwrap();
+        assert!(sio.read_register(0x100).is_err());
+        assert!(sio.write_register(0x100, 0).is_err());
+    }
+
+    #[test]
+    fn pins_follow_sio_only_when_selected() {
+        let gpio = shared(GPIO::new());
+        let mut sio = SIO::new(gpio.clone());
+        sio.set_out(0b11, 0b11);
+        sio.set_oe(0b11, 0b11);
+        assert_eq!(sio.gpio_in() & 0b11, 0);
+
+        lock(&gpio).set_function(1, FunctionSelect::SIO);
+        assert_eq!(sio.gpio_in() & 0b11, 0b10);
+        assert_eq!(sio.read_register(GPIO_IN).unwrap() & 0b11, 0b10);
+        assert_eq!(sio.gpio_oe(), 0b11);
+    }
+}
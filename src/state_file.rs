@@ -0,0 +1,36 @@
+use crate::state_machine::PIOStateMachine;
+
+use serde::{Deserialize, Serialize};
+
+/// Emulator state that has no home in the register block. Saved as a JSON side-file next to the
+/// raw register image so a dump taken with a debug probe can be completed by hand.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct InternalState {
+    pub state_machines: Vec<PIOStateMachine>,
+    pub irq_flags: [u8; 8],
+    pub delay_count: u32,
+}
+
+impl InternalState {
+    pub fn to_json(&self) -> Result<std::string::String, std::string::String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|err| format!("Failed to serialize internal state : {}", err))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, std::string::String> {
+        let state: InternalState = serde_json::from_str(json)
+            .map_err(|err| format!("Failed to parse internal state : {}", err))?;
+
+        if state.state_machines.len() != 4 {
+            return Err(format!(
+                "Expected 4 state machines, found {}",
+                state.state_machines.len()
+            ));
+        }
+        for sm in state.state_machines.iter() {
+            sm.validate()?;
+        }
+
+        Ok(state)
+    }
+}
@@ -1,6 +1,7 @@
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct PIOStateMachine {
     osr: u32,
     isr: u32,
@@ -15,6 +16,39 @@ pub struct PIOStateMachine {
 }
 
 impl PIOStateMachine {
+    /// Returns the state machine to its power-on state, emptying both FIFOs
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Clears the internal state that `CTRL.SM_RESTART` resets. FIFO contents, scratch registers
+    /// and the program counter are kept.
+    pub fn restart(&mut self) {
+        self.osr = 0;
+        self.isr = 0;
+        self.output_shift_counter = 0;
+        self.input_shift_counter = 0;
+    }
+
+    pub fn restart_clock_divider(&mut self) {
+        self.clock_divider = 0;
+    }
+
+    /// Checks invariants that the rest of the emulator relies on, for state loaded from outside
+    pub fn validate(&self) -> Result<(), std::string::String> {
+        if self.pc > 31 {
+            return Err(format!("Invalid PC : {}", self.pc));
+        }
+        if self.tx_fifo.len() > 4 || self.rx_fifo.len() > 4 {
+            return Err(format!(
+                "FIFO deeper than 4 entries : tx {}, rx {}",
+                self.tx_fifo.len(),
+                self.rx_fifo.len()
+            ));
+        }
+        Ok(())
+    }
+
     pub fn get_pc(&self) -> u32 {
         self.pc
     }
@@ -58,6 +92,22 @@ impl PIOStateMachine {
         }
     }
 
+    pub fn rx_fifo_empty(&self) -> bool {
+        self.rx_fifo.is_empty()
+    }
+
+    pub fn rx_fifo_level(&self) -> u32 {
+        self.rx_fifo.len() as u32
+    }
+
+    pub fn pop_from_rx_fifo(&mut self) -> Result<u32, std::string::String> {
+        if let Some(val) = self.rx_fifo.pop_front() {
+            Ok(val)
+        } else {
+            Err("Tried to pop data from an empty rx_fifo".to_string())
+        }
+    }
+
     pub fn get_isr(&self) -> u32 {
         self.isr
     }
@@ -81,6 +131,23 @@ impl PIOStateMachine {
         self.tx_fifo.is_empty()
     }
 
+    pub fn tx_fifo_full(&self) -> bool {
+        self.tx_fifo.len() >= 4
+    }
+
+    pub fn tx_fifo_level(&self) -> u32 {
+        self.tx_fifo.len() as u32
+    }
+
+    pub fn push_to_tx_fifo(&mut self, value: u32) -> Result<(), std::string::String> {
+        if self.tx_fifo_full() {
+            return Err("TX FIFO is full!".to_string());
+        }
+
+        self.tx_fifo.push_back(value);
+        Ok(())
+    }
+
     pub fn pop_from_tx_fifo(&mut self) -> Result<u32, std::string::String> {
         if let Some(val) = self.tx_fifo.pop_front() {
             Ok(val)
@@ -0,0 +1,201 @@
+use std::sync::mpsc;
+use std::thread;
+
+use memoffset::offset_of;
+
+use crate::gpio;
+use crate::memory_backing::PIOMemoryBacking;
+use crate::PIO;
+
+type Reply<T> = mpsc::Sender<Result<T, std::string::String>>;
+
+enum Command {
+    Step(usize, Reply<()>),
+    Run,
+    Pause(Reply<()>),
+    ReadRegister(usize, Reply<u32>),
+    WriteRegister(usize, u32, Reply<()>),
+    SetInputLevel(usize, u8),
+    AddStimulus(usize, Vec<(u64, u8)>),
+}
+
+/// Runs a `PIO` on a background thread. The host talks to it through channels, so feeding FIFOs
+/// and stimulus never has to wait for the emulation to reach a stopping point. Pin state can
+/// also be inspected directly through the shared GPIO.
+pub struct Worker {
+    commands: mpsc::Sender<Command>,
+    gpio: gpio::SharedGPIO,
+    thread: thread::JoinHandle<PIO>,
+}
+
+impl Worker {
+    pub fn spawn(pio: PIO) -> Self {
+        let gpio = pio.gpio();
+        let (commands, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || Worker::serve(pio, receiver));
+        Worker {
+            commands,
+            gpio,
+            thread,
+        }
+    }
+
+    pub fn gpio(&self) -> gpio::SharedGPIO {
+        self.gpio.clone()
+    }
+
+    pub fn step(&self, steps: usize) -> Result<(), std::string::String> {
+        self.request(|reply| Command::Step(steps, reply))
+    }
+
+    /// Keeps stepping until `pause` is called or an instruction fails
+    pub fn run(&self) -> Result<(), std::string::String> {
+        self.send(Command::Run)
+    }
+
+    /// Stops a free running emulation. Returns the error that stopped it early, if any.
+    pub fn pause(&self) -> Result<(), std::string::String> {
+        self.request(Command::Pause)
+    }
+
+    pub fn read_register(&self, offset: usize) -> Result<u32, std::string::String> {
+        self.request(|reply| Command::ReadRegister(offset, reply))
+    }
+
+    pub fn write_register(&self, offset: usize, value: u32) -> Result<(), std::string::String> {
+        self.request(|reply| Command::WriteRegister(offset, value, reply))
+    }
+
+    pub fn push_tx_fifo(&self, sm_id: u32, value: u32) -> Result<(), std::string::String> {
+        self.write_register(
+            offset_of!(PIOMemoryBacking, TXF0) + sm_id as usize * 4,
+            value,
+        )
+    }
+
+    pub fn pop_rx_fifo(&self, sm_id: u32) -> Result<u32, std::string::String> {
+        self.read_register(offset_of!(PIOMemoryBacking, RXF0) + sm_id as usize * 4)
+    }
+
+    pub fn set_input_level(&self, idx: usize, level: u8) -> Result<(), std::string::String> {
+        self.send(Command::SetInputLevel(idx, level))
+    }
+
+    pub fn add_stimulus(
+        &self,
+        idx: usize,
+        waveform: &[(u64, u8)],
+    ) -> Result<(), std::string::String> {
+        self.send(Command::AddStimulus(idx, waveform.to_vec()))
+    }
+
+    /// Stops the worker thread and hands the `PIO` back
+    pub fn join(self) -> Result<PIO, std::string::String> {
+        drop(self.commands);
+        self.thread
+            .join()
+            .map_err(|_| "Emulation thread panicked".to_string())
+    }
+
+    fn send(&self, command: Command) -> Result<(), std::string::String> {
+        self.commands
+            .send(command)
+            .map_err(|_| "Emulation thread has stopped".to_string())
+    }
+
+    fn request<T>(
+        &self,
+        command: impl FnOnce(Reply<T>) -> Command,
+    ) -> Result<T, std::string::String> {
+        let (reply, receiver) = mpsc::channel();
+        self.send(command(reply))?;
+        receiver
+            .recv()
+            .map_err(|_| "Emulation thread has stopped".to_string())?
+    }
+
+    fn serve(mut pio: PIO, receiver: mpsc::Receiver<Command>) -> PIO {
+        let mut running = false;
+        // Error that stopped a free running emulation, reported by the next `pause`
+        let mut run_error = None;
+        loop {
+            let command = if running {
+                match receiver.try_recv() {
+                    Ok(command) => command,
+                    Err(mpsc::TryRecvError::Empty) => {
+                        if let Err(err) = pio.step() {
+                            running = false;
+                            run_error = Some(err);
+                        }
+                        continue;
+                    }
+                    Err(mpsc::TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match receiver.recv() {
+                    Ok(command) => command,
+                    Err(_) => break,
+                }
+            };
+
+            // Replies only fail to send when the host stopped waiting for them
+            match command {
+                Command::Step(steps, reply) => {
+                    let _ = reply.send(pio.step_n(steps));
+                }
+                Command::Run => {
+                    running = true;
+                    run_error = None;
+                }
+                Command::Pause(reply) => {
+                    running = false;
+                    let _ = reply.send(run_error.take().map_or(Ok(()), Err));
+                }
+                Command::ReadRegister(offset, reply) => {
+                    let _ = reply.send(pio.read_register(offset));
+                }
+                Command::WriteRegister(offset, value, reply) => {
+                    let _ = reply.send(pio.write_register(offset, value));
+                }
+                Command::SetInputLevel(idx, level) => {
+                    gpio::lock(&pio.gpio()).set_input_level(idx, level)
+                }
+                Command::AddStimulus(idx, waveform) => {
+                    gpio::lock(&pio.gpio()).add_stimulus(idx, &waveform)
+                }
+            }
+        }
+        pio
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SM0_ADDR: usize = 0xD4;
+
+    #[test]
+    fn input_level_releases_a_waiting_worker() {
+        let mut pio = PIO::new(0, 0, gpio::shared(gpio::GPIO::default()));
+        // wait 1 gpio 2
+        // set x, 1
+        pio.write_instructions(0, &[0x2082, 0xe021]).unwrap();
+        let worker = Worker::spawn(pio);
+
+        worker.step(5).unwrap();
+        assert_eq!(worker.read_register(SM0_ADDR).unwrap(), 0);
+
+        // A free running worker stuck on the WAIT still answers
+        worker.run().unwrap();
+        worker.pause().unwrap();
+
+        worker.set_input_level(2, 1).unwrap();
+        worker.step(1).unwrap();
+        assert_eq!(worker.read_register(SM0_ADDR).unwrap(), 1);
+        assert_eq!(gpio::lock(&worker.gpio()).get_idx(2), 1);
+
+        let mut pio = worker.join().unwrap();
+        assert_eq!(pio.get_sm(0).unwrap().get_pc(), 1);
+    }
+}